@@ -1,28 +1,114 @@
-use std;
-use SystrayError;
+use crate::builder::TrayOptions;
+use crate::menu_builder::Populate;
+use crate::{
+    Capabilities, Capability, Event, Icon, Label, Notification, Status, SystrayError, Tooltip,
+    TooltipFit, TrayId,
+};
+use std::sync::atomic::AtomicU32;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+pub type IconHandle = ();
+
+fn unavailable() -> SystrayError {
+    SystrayError::unavailable("There is no tray backend for this platform yet")
+}
 
 pub struct Window {}
 
 impl Window {
-    pub fn new() -> Result<Window, SystrayError> {
-        Err(SystrayError::NotImplementedError)
+    pub(crate) fn new(
+        _: TrayId,
+        _: &TrayOptions,
+        _: Sender<Event>,
+    ) -> Result<Window, SystrayError> {
+        Err(unavailable())
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    pub fn show(&self) -> Result<(), SystrayError> {
+        Ok(())
+    }
+
+    pub fn quit(&mut self) -> Result<(), SystrayError> {
+        Ok(())
+    }
+
+    pub fn set_tooltip(&self, _: &Tooltip) -> Result<TooltipFit, SystrayError> {
+        Err(SystrayError::Unsupported(Capability::Tooltips))
+    }
+
+    pub fn add_menu_entry(
+        &self,
+        _: u64,
+        _: u32,
+        _: u32,
+        _: &Label,
+        _: Option<Icon>,
+        _: Option<bool>,
+    ) -> Result<(), SystrayError> {
+        Err(unavailable())
+    }
+
+    pub fn add_menu_group(
+        &self,
+        _: u64,
+        _: u32,
+        _: u32,
+        _: &Label,
+        _: Option<Icon>,
+    ) -> Result<u64, SystrayError> {
+        Err(unavailable())
     }
-    pub fn quit(&self) {
-        unimplemented!()
+
+    pub fn add_dynamic_submenu(
+        &self,
+        _: u64,
+        _: u32,
+        _: u32,
+        _: &Label,
+        _: Populate,
+        _: Arc<AtomicU32>,
+    ) -> Result<u64, SystrayError> {
+        Err(unavailable())
+    }
+
+    pub fn add_menu_separator(&self, _: u64, _: u32, _: u32) -> Result<(), SystrayError> {
+        Err(unavailable())
+    }
+
+    pub fn set_menu_item_tooltip(&self, _: u32, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::Unsupported(Capability::ItemTooltips))
     }
-    pub fn set_tooltip(&self, _: &String) -> Result<(), SystrayError> {
-        unimplemented!()
+
+    pub fn set_menu_item_checked(&self, _: u32, _: bool) -> Result<(), SystrayError> {
+        Err(unavailable())
     }
-    pub fn add_menu_item<F>(&self, _: &String, _: F) -> Result<u32, SystrayError>
-    where
-        F: std::ops::Fn(&Window) -> () + 'static,
-    {
-        unimplemented!()
+
+    pub fn set_icon_from_file(&self, _: &str) -> Result<(), SystrayError> {
+        Err(unavailable())
     }
-    pub fn wait_for_message(&mut self) {
-        unimplemented!()
+
+    pub fn set_icon_from_resource(&self, _: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::Unsupported(Capability::ResourceIcons))
     }
+
+    pub fn set_attention_icon_from_file(&self, _: &str) -> Result<(), SystrayError> {
+        Err(unavailable())
+    }
+
+    pub fn set_status(&self, _: Status) -> Result<(), SystrayError> {
+        Err(SystrayError::Unsupported(Capability::Status))
+    }
+
     pub fn set_icon_from_buffer(&self, _: &[u8], _: u32, _: u32) -> Result<(), SystrayError> {
-        unimplemented!()
+        Err(unavailable())
+    }
+
+    pub fn notify(&self, _: &Notification) -> Result<u32, SystrayError> {
+        Err(SystrayError::Unsupported(Capability::Notifications))
     }
 }
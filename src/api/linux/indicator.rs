@@ -0,0 +1,103 @@
+// AppIndicator, wrapped like the libappindicator crate does, which leaves no
+// way to pick the category or the icon theme path.
+use super::sni::{self, ToolTip};
+use crate::builder::{Category, TrayOptions};
+use glib::translate::ToGlibPtr;
+use gobject_sys::{g_object_unref, GObject};
+use gtk;
+use libappindicator_sys::{
+    app_indicator_new, app_indicator_set_attention_icon_full, app_indicator_set_icon_full,
+    app_indicator_set_icon_theme_path, app_indicator_set_menu, app_indicator_set_status,
+    app_indicator_set_title, AppIndicator, AppIndicatorCategory, AppIndicatorStatus,
+};
+
+pub struct Indicator {
+    air: *mut AppIndicator,
+    // Object path of the item on the session bus.
+    path: String,
+}
+
+fn category(category: Category) -> AppIndicatorCategory {
+    match category {
+        Category::ApplicationStatus => {
+            AppIndicatorCategory::APP_INDICATOR_CATEGORY_APPLICATION_STATUS
+        }
+        Category::Communications => AppIndicatorCategory::APP_INDICATOR_CATEGORY_COMMUNICATIONS,
+        Category::SystemServices => AppIndicatorCategory::APP_INDICATOR_CATEGORY_SYSTEM_SERVICES,
+        Category::Hardware => AppIndicatorCategory::APP_INDICATOR_CATEGORY_HARDWARE,
+    }
+}
+
+impl Indicator {
+    // The id names the D-Bus object of the item, so it has to differ between
+    // the trays of the process.
+    pub fn new(id: &str, options: &TrayOptions) -> Indicator {
+        let indicator = Indicator {
+            air: unsafe {
+                app_indicator_new(
+                    id.to_glib_none().0,
+                    "".to_glib_none().0,
+                    category(options.category),
+                )
+            },
+            path: sni::item_path(id),
+        };
+        if let Some(ref title) = options.title {
+            unsafe {
+                app_indicator_set_title(indicator.air, title.to_glib_none().0);
+            }
+        }
+        if let Some(ref path) = options.icon_theme_path {
+            unsafe {
+                app_indicator_set_icon_theme_path(indicator.air, path.to_glib_none().0);
+            }
+        }
+        indicator
+    }
+
+    pub fn set_status(&mut self, status: AppIndicatorStatus) {
+        unsafe {
+            app_indicator_set_status(self.air, status);
+        }
+    }
+
+    pub fn set_menu(&mut self, menu: &gtk::Menu) {
+        unsafe {
+            app_indicator_set_menu(self.air, menu.to_glib_none().0);
+        }
+    }
+
+    pub fn set_icon_full(&mut self, name: &str, desc: &str) {
+        unsafe {
+            app_indicator_set_icon_full(self.air, name.to_glib_none().0, desc.to_glib_none().0);
+        }
+    }
+
+    pub fn set_tool_tip(&mut self, tool_tip: ToolTip) {
+        sni::set_tool_tip(&self.path, tool_tip);
+    }
+
+    pub fn remove_tool_tip(&mut self) {
+        sni::remove(&self.path);
+    }
+
+    pub fn set_attention_icon_full(&mut self, name: &str, desc: &str) {
+        unsafe {
+            app_indicator_set_attention_icon_full(
+                self.air,
+                name.to_glib_none().0,
+                desc.to_glib_none().0,
+            );
+        }
+    }
+}
+
+// app_indicator_new hands over a reference, dropping it takes the item off
+// the bus.
+impl Drop for Indicator {
+    fn drop(&mut self) {
+        unsafe {
+            g_object_unref(self.air as *mut GObject);
+        }
+    }
+}
@@ -1,39 +1,247 @@
+use crate::builder::TrayOptions;
+use crate::menu_builder::{BuilderItem, MenuBuilder, Populate};
+use crate::{
+    Capabilities, Capability, Event, Icon, Label, Markup, MenuLifecycleEvent, Notification, Status,
+    SystrayError, SystrayEvent, Tooltip, TooltipFit, TrayId,
+};
 use glib;
+use glib::translate::ToGlibPtr;
 use gtk::{
-    self, Inhibit, Menu, MenuItemExt, MenuShellExt, Widget, WidgetExt, Window as GTKWindow,
-    WindowType,
+    self, BinExt, Cast, CheckMenuItemExt, ContainerExt, LabelExt, MenuExt, MenuItemExt,
+    MenuShellExt, StatusIconExt, WidgetExt,
 };
-use libappindicator::{AppIndicator, AppIndicatorStatus};
+use libappindicator_sys::AppIndicatorStatus;
 use std;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::sync::mpsc::{channel, Sender};
+use std::env;
+use std::fs::File;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use {SystrayError, SystrayEvent};
+use std::time::{Duration, Instant};
+
+mod indicator;
+mod notify;
+mod sni;
+mod watcher;
+mod xembed;
+
+use self::indicator::Indicator;
+use self::notify::Notifier;
+
+pub type IconHandle = ();
+
+/// Tray protocols available on Linux.
+///
+/// AppIndicator (StatusNotifierItem over D-Bus) is used when a
+/// StatusNotifierHost is registered, XEmbed otherwise. Setting
+/// `SYSTRAY_BACKEND` to `appindicator` or `xembed` in the environment skips
+/// the detection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    AppIndicator,
+    XEmbed,
+}
+
+impl Backend {
+    fn from_env() -> Option<Backend> {
+        match env::var("SYSTRAY_BACKEND").as_ref().map(String::as_str) {
+            Ok("appindicator") => Some(Backend::AppIndicator),
+            Ok("xembed") => Some(Backend::XEmbed),
+            Ok(b) => {
+                warn!("Ignoring unknown SYSTRAY_BACKEND {:?}", b);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn detect() -> Result<Backend, SystrayError> {
+        match watcher::host_registered() {
+            Ok(true) => return Ok(Backend::AppIndicator),
+            Ok(false) => warn!("No StatusNotifierHost is registered"),
+            Err(e) => warn!("Can't ask the StatusNotifierWatcher for a host: {}", e),
+        }
+        if env::var_os("DISPLAY").is_none() {
+            return Err(SystrayError::unavailable(
+                "No StatusNotifierHost is registered and there is no X display to fall back to XEmbed",
+            ));
+        }
+        info!("Falling back to the XEmbed system tray");
+        Ok(Backend::XEmbed)
+    }
+}
+
+enum Tray {
+    AppIndicator(Indicator),
+    XEmbed(gtk::StatusIcon),
+}
 
 // Gtk specific struct that will live only in the Gtk thread, since a lot of the
 // base types involved don't implement Send (for good reason).
 pub struct GtkSystrayApp {
+    tray_id: TrayId,
     menu: gtk::Menu,
-    ai: RefCell<AppIndicator>,
+    tray: RefCell<Tray>,
     menu_items: RefCell<HashMap<u32, gtk::MenuItem>>,
-    event_tx: Sender<SystrayEvent>,
+    submenus: RefCell<HashMap<u64, gtk::Menu>>,
+    // Set while `set_menu_item_checked` toggles an item, which activates it
+    // like a click would.
+    setting_check: Rc<Cell<bool>>,
+    accel_group: gtk::AccelGroup,
+    event_tx: Sender<Event>,
+    status: Cell<Status>,
+    // Set by `show`, until then the tray stays hidden.
+    shown: Cell<bool>,
+    // GtkStatusIcon has no attention icon of its own, so for XEmbed we swap
+    // between the two files ourselves.
+    icon: RefCell<Option<String>>,
+    attention_icon: RefCell<Option<String>>,
 }
 
-thread_local!(static GTK_STASH: RefCell<Option<GtkSystrayApp>> = RefCell::new(None));
+// GTK can only be used from the thread that initialized it, so all trays of
+// the process share one GTK thread, which keeps their GtkSystrayApps here.
+thread_local!(
+    static GTK_STASH: RefCell<HashMap<TrayId, GtkSystrayApp>> = RefCell::new(HashMap::new())
+);
+
+// Set once the GTK thread runs. It is started by the first tray and kept
+// until the process exits, as GTK can't be initialized again elsewhere.
+static GTK_THREAD: Mutex<Option<GtkThread>> = Mutex::new(None);
+
+enum GtkThread {
+    Running(thread::JoinHandle<()>),
+    // Joined once it was found to be gone, with the message it panicked
+    // with, which is kept to tell every tray.
+    Ended(Option<String>),
+}
+
+// Tells the trays when the GTK thread ends, even by panicking. Each
+// Application keeps a sender of its own for Wakers, so it can't rely on the
+// channel closing.
+struct QuitOnExit;
+
+impl Drop for QuitOnExit {
+    fn drop(&mut self) {
+        let _ = GTK_STASH.try_with(|stash| {
+            if let Ok(apps) = stash.try_borrow() {
+                for app in apps.values() {
+                    app.event_tx.send(Event::Quit).ok();
+                }
+            }
+        });
+    }
+}
+
+fn start_gtk_thread() -> Result<(), SystrayError> {
+    let mut gtk_thread = GTK_THREAD.lock().unwrap();
+    if gtk_thread.is_some() {
+        return Ok(());
+    }
+    let (tx, rx) = channel();
+    let handle = thread::spawn(move || {
+        let _quit = QuitOnExit;
+        if let Err(e) = gtk::init() {
+            tx.send(Err(SystrayError::BackendUnavailable {
+                reason: "GTK failed to initialize".to_string(),
+                source: Some(Box::new(e)),
+            }))
+            .ok();
+            return;
+        }
+        tx.send(Ok(())).ok();
+        gtk::main();
+    });
+    rx.recv().map_err(|_| SystrayError::ThreadGone)??;
+    *gtk_thread = Some(GtkThread::Running(handle));
+    Ok(())
+}
+
+// Translates accelerators written like "Ctrl+Shift+Q" to GTK's
+// "<Control><Shift>q".
+fn gtk_accelerator(accelerator: &str) -> String {
+    let mut parts: Vec<&str> = accelerator.split('+').map(str::trim).collect();
+    let key = parts.pop().unwrap_or("");
+    let mut gtk = String::new();
+    for modifier in parts {
+        gtk.push_str(match modifier.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => "<Control>",
+            "shift" => "<Shift>",
+            "alt" => "<Alt>",
+            "super" | "win" | "meta" | "cmd" => "<Super>",
+            _ => "",
+        });
+    }
+    match key.to_ascii_lowercase().as_str() {
+        "del" => gtk.push_str("Delete"),
+        "esc" => gtk.push_str("Escape"),
+        "enter" => gtk.push_str("Return"),
+        "ins" => gtk.push_str("Insert"),
+        _ if key.chars().count() == 1 => gtk.push_str(&key.to_lowercase()),
+        _ => gtk.push_str(key),
+    }
+    gtk
+}
+
+// Whether the GTK thread is still there to run what is sent to it. Once it
+// is gone, it is joined, and a panic is reported like on Windows.
+fn check_gtk_thread() -> Result<(), SystrayError> {
+    let mut gtk_thread = GTK_THREAD.lock().unwrap();
+    match *gtk_thread {
+        Some(GtkThread::Running(ref handle)) if !handle.is_finished() => return Ok(()),
+        Some(GtkThread::Running(_)) => {}
+        _ => return Err(SystrayError::ThreadGone),
+    }
+    if let Some(GtkThread::Running(handle)) = gtk_thread.take() {
+        let message = handle.join().err().map(|payload| {
+            payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default()
+        });
+        *gtk_thread = Some(GtkThread::Ended(message));
+    }
+    match *gtk_thread {
+        Some(GtkThread::Ended(Some(ref message))) => {
+            Err(SystrayError::ThreadPanicked(message.clone()))
+        }
+        _ => Err(SystrayError::ThreadGone),
+    }
+}
 
-pub struct MenuItemInfo {
-    mid: u32,
-    title: String,
-    tooltip: String,
-    disabled: bool,
-    checked: bool,
+// Waits for the answer to something sent to the GTK thread. Should the
+// thread be gone, the main context keeps what was sent along with its end of
+// the channel, so that `recv` alone would wait forever.
+fn wait_for_gtk_thread<T>(rx: Receiver<T>) -> Result<T, SystrayError> {
+    loop {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(answer) => return Ok(answer),
+            Err(RecvTimeoutError::Timeout) => check_gtk_thread()?,
+            Err(RecvTimeoutError::Disconnected) => {
+                check_gtk_thread()?;
+                return Err(SystrayError::ThreadGone);
+            }
+        }
+    }
 }
 
-type Callback = Box<(Fn(&GtkSystrayApp) -> () + 'static)>;
+// GTK only logs icons it can't load, so files that can't be read are
+// reported here, before they are handed to it.
+fn check_icon_file(path: &str) -> Result<(), SystrayError> {
+    File::open(path)
+        .map(|_| ())
+        .map_err(|source| SystrayError::IconLoad {
+            path: path.to_string(),
+            source,
+        })
+}
 
 // Convenience function to clean up thread local unwrapping
-fn run_on_gtk_thread<F>(f: F)
+fn run_on_gtk_thread<F>(tray_id: TrayId, f: F)
 where
     F: std::ops::Fn(&GtkSystrayApp) -> () + Send + 'static,
 {
@@ -42,8 +250,7 @@ where
     glib::idle_add(move || {
         GTK_STASH.with(|stash| {
             let stash = stash.borrow();
-            let stash = stash.as_ref();
-            if let Some(stash) = stash {
+            if let Some(stash) = stash.get(&tray_id) {
                 f(stash);
             }
         });
@@ -52,130 +259,644 @@ where
 }
 
 impl GtkSystrayApp {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<GtkSystrayApp, SystrayError> {
-        if let Err(e) = gtk::init() {
-            return Err(SystrayError::OsError(format!("{}", "Gtk init error!")));
-        }
-        let mut m = gtk::Menu::new();
-        let mut ai = AppIndicator::new("", "");
-        ai.set_status(AppIndicatorStatus::APP_INDICATOR_STATUS_ACTIVE);
-        ai.set_menu(&mut m);
-        Ok(GtkSystrayApp {
+    pub(crate) fn new(
+        tray_id: TrayId,
+        options: &TrayOptions,
+        event_tx: Sender<Event>,
+        backend: Backend,
+    ) -> Result<GtkSystrayApp, SystrayError> {
+        let m = gtk::Menu::new();
+        let accel_group = gtk::AccelGroup::new();
+        m.set_accel_group(&accel_group);
+        let tray = match backend {
+            Backend::AppIndicator => {
+                let id = match options.id {
+                    Some(ref id) => id.clone(),
+                    None => format!("{}-{}", app_name(), tray_id.0),
+                };
+                let mut ai = Indicator::new(&id, options);
+                ai.set_menu(&m);
+                Tray::AppIndicator(ai)
+            }
+            Backend::XEmbed => Tray::XEmbed(xembed::new_status_icon(&m)),
+        };
+        let app = GtkSystrayApp {
+            tray_id,
             menu: m,
-            ai: RefCell::new(ai),
+            tray: RefCell::new(tray),
             menu_items: RefCell::new(HashMap::new()),
+            submenus: RefCell::new(HashMap::new()),
+            setting_check: Rc::new(Cell::new(false)),
+            accel_group,
             event_tx: event_tx,
-        })
+            status: Cell::new(Status::Active),
+            shown: Cell::new(false),
+            icon: RefCell::new(None),
+            attention_icon: RefCell::new(None),
+        };
+        app.watch_menu(&app.menu, 0);
+        Ok(app)
+    }
+
+    // Only fires when GTK draws the menu, with AppIndicator the
+    // StatusNotifierHost shows it over dbusmenu and these stay silent.
+    fn watch_menu(&self, menu: &gtk::Menu, menu_id: u64) {
+        let tx = self.event_tx.clone();
+        menu.connect_show(move |_| {
+            tx.send(Event::Lifecycle(MenuLifecycleEvent::MenuOpened(menu_id)))
+                .ok();
+        });
+        let tx = self.event_tx.clone();
+        menu.connect_hide(move |_| {
+            tx.send(Event::Lifecycle(MenuLifecycleEvent::MenuClosed(menu_id)))
+                .ok();
+        });
     }
 
-    pub fn systray_menu_selected(&self, menu_id: u32) {
+    fn get_menu(&self, submenu: u64) -> Option<gtk::Menu> {
+        if submenu == 0 {
+            return Some(self.menu.clone());
+        }
+        self.submenus.borrow().get(&submenu).cloned()
+    }
+
+    pub fn systray_menu_selected(&self, menu_id: u64, item_id: u32, checked: Option<bool>) {
         self.event_tx
-            .send(SystrayEvent {
-                menu_index: menu_id as u32,
-            })
+            .send(Event::Menu(SystrayEvent {
+                menu_id,
+                item_id,
+                checked,
+                timestamp: Instant::now(),
+            }))
             .ok();
     }
 
-    pub fn add_menu_separator(&self, item_idx: u32) {
-        //let mut menu_items = self.menu_items.borrow_mut();
+    // Menu items only take plain text labels, the markup goes to their child.
+    // That child is an accel label, which shows the accelerators added to the
+    // item.
+    fn set_label(&self, item: &gtk::MenuItem, label: &Label) {
+        item.set_label("");
+        if let Some(child) = item
+            .get_child()
+            .and_then(|c| c.downcast::<gtk::Label>().ok())
+        {
+            child.set_markup_with_mnemonic(&label.text.to_pango_mnemonic_markup());
+        }
+        if let Some(ref accelerator) = label.accelerator {
+            let (key, mods) = gtk::accelerator_parse(&gtk_accelerator(accelerator));
+            if key == 0 {
+                warn!("Ignoring unknown accelerator {:?}", accelerator);
+                return;
+            }
+            item.add_accelerator(
+                "activate",
+                &self.accel_group,
+                key,
+                mods,
+                gtk::AccelFlags::VISIBLE,
+            );
+        }
+    }
+
+    pub fn add_menu_separator(&self, submenu: u64) {
+        let menu = match self.get_menu(submenu) {
+            Some(m) => m,
+            None => return,
+        };
         let m = gtk::SeparatorMenuItem::new();
-        self.menu.append(&m);
-        //menu_items.insert(item_idx, m);
-        self.menu.show_all();
+        menu.append(&m);
+        menu.show_all();
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &String) {
+    pub fn add_menu_entry(
+        &self,
+        submenu: u64,
+        item_idx: u32,
+        label: &Label,
+        checked: Option<bool>,
+    ) {
+        let menu = match self.get_menu(submenu) {
+            Some(m) => m,
+            None => return,
+        };
         let mut menu_items = self.menu_items.borrow_mut();
         if menu_items.contains_key(&item_idx) {
             let m: &gtk::MenuItem = menu_items.get(&item_idx).unwrap();
-            m.set_label(item_name);
-            self.menu.show_all();
+            self.set_label(m, label);
+            menu.show_all();
             return;
         }
-        let m = gtk::MenuItem::new_with_label(item_name);
-        self.menu.append(&m);
-        m.connect_activate(move |_| {
-            run_on_gtk_thread(move |stash: &GtkSystrayApp| {
-                stash.systray_menu_selected(item_idx);
+        let m: gtk::MenuItem = match checked {
+            Some(active) => {
+                let c = gtk::CheckMenuItem::new();
+                c.set_active(active);
+                c.upcast()
+            }
+            None => gtk::MenuItem::new(),
+        };
+        self.set_label(&m, label);
+        menu.append(&m);
+        let tray_id = self.tray_id;
+        let setting_check = self.setting_check.clone();
+        m.connect_activate(move |m| {
+            if setting_check.get() {
+                return;
+            }
+            // Check items have already toggled themselves by now.
+            let checked = m
+                .clone()
+                .downcast::<gtk::CheckMenuItem>()
+                .ok()
+                .map(|c| c.get_active());
+            run_on_gtk_thread(tray_id, move |stash: &GtkSystrayApp| {
+                stash.systray_menu_selected(submenu, item_idx, checked);
             });
         });
         menu_items.insert(item_idx, m);
-        self.menu.show_all();
+        menu.show_all();
+    }
+
+    pub fn add_menu_group(&self, submenu: u64, item_idx: u32, label: &Label, group: u64) {
+        let menu = match self.get_menu(submenu) {
+            Some(m) => m,
+            None => return,
+        };
+        let m = gtk::MenuItem::new();
+        self.set_label(&m, label);
+        let group_menu = gtk::Menu::new();
+        group_menu.set_accel_group(&self.accel_group);
+        self.watch_menu(&group_menu, group);
+        m.set_submenu(Some(&group_menu));
+        menu.append(&m);
+        self.menu_items.borrow_mut().insert(item_idx, m);
+        self.submenus.borrow_mut().insert(group, group_menu);
+        menu.show_all();
+    }
+
+    pub fn add_dynamic_submenu(
+        &self,
+        submenu: u64,
+        item_idx: u32,
+        label: &Label,
+        group: u64,
+        populate: Populate,
+        ids: Arc<AtomicU32>,
+    ) {
+        self.add_menu_group(submenu, item_idx, label, group);
+        let (item, group_menu) = match (
+            self.menu_items.borrow().get(&item_idx),
+            self.submenus.borrow().get(&group),
+        ) {
+            (Some(i), Some(m)) => (i.clone(), m.clone()),
+            _ => return,
+        };
+        // The items have to be in place before the menu shows, so populate
+        // right here on the GTK thread instead of going through
+        // run_on_gtk_thread.
+        let tray_id = self.tray_id;
+        let on_open = move || {
+            GTK_STASH.with(|stash| {
+                if let Some(stash) = stash.borrow().get(&tray_id) {
+                    stash.populate_menu(group, &populate, ids.clone());
+                }
+            });
+        };
+        match *self.tray.borrow() {
+            // The menu is drawn by the StatusNotifierHost, dbusmenu turns its
+            // AboutToShow calls into activations of the parent item.
+            Tray::AppIndicator(_) => {
+                item.connect_activate(move |_| on_open());
+            }
+            Tray::XEmbed(_) => {
+                group_menu.connect_show(move |_| on_open());
+            }
+        }
+    }
+
+    // Replaces the items of a dynamic submenu with what `populate` adds.
+    fn populate_menu(&self, submenu: u64, populate: &Populate, ids: Arc<AtomicU32>) {
+        let menu = match self.get_menu(submenu) {
+            Some(m) => m,
+            None => return,
+        };
+        let mut builder = MenuBuilder::new(ids);
+        populate(&mut builder);
+        let old = menu.get_children();
+        self.menu_items
+            .borrow_mut()
+            .retain(|_, item| !old.contains(&item.clone().upcast::<gtk::Widget>()));
+        for widget in &old {
+            menu.remove(widget);
+        }
+        for item in builder.items {
+            match item {
+                BuilderItem::Entry { id, label, checked } => {
+                    self.add_menu_entry(submenu, id, &label, checked)
+                }
+                BuilderItem::Separator => self.add_menu_separator(submenu),
+            }
+        }
+    }
+
+    pub fn set_menu_item_tooltip(&self, item_idx: u32, tooltip: &str) {
+        if let Some(m) = self.menu_items.borrow().get(&item_idx) {
+            m.set_tooltip_text(tooltip);
+            // libdbusmenu-gtk exports the accessible name, when it differs
+            // from the label, as accessible-desc, the only place for the text
+            // where the host draws the menu.
+            if let Tray::AppIndicator(_) = *self.tray.borrow() {
+                unsafe {
+                    let widget: *mut gtk_sys::GtkMenuItem = m.to_glib_none().0;
+                    let accessible = gtk_sys::gtk_widget_get_accessible(widget as *mut _);
+                    if !accessible.is_null() {
+                        atk_sys::atk_object_set_name(accessible, tooltip.to_glib_none().0);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn set_menu_item_checked(&self, item_idx: u32, checked: bool) {
+        let item = self
+            .menu_items
+            .borrow()
+            .get(&item_idx)
+            .and_then(|m| m.clone().downcast::<gtk::CheckMenuItem>().ok());
+        if let Some(item) = item {
+            self.setting_check.set(true);
+            item.set_active(checked);
+            self.setting_check.set(false);
+        }
+    }
+
+    pub fn set_icon_from_file(&self, file: &str) {
+        *self.icon.borrow_mut() = Some(file.to_owned());
+        match *self.tray.borrow_mut() {
+            Tray::AppIndicator(ref mut ai) => ai.set_icon_full(file, "icon"),
+            Tray::XEmbed(_) => {}
+        }
+        self.update_xembed_icon();
+    }
+
+    pub fn set_attention_icon_from_file(&self, file: &str) {
+        *self.attention_icon.borrow_mut() = Some(file.to_owned());
+        match *self.tray.borrow_mut() {
+            Tray::AppIndicator(ref mut ai) => ai.set_attention_icon_full(file, "attention"),
+            Tray::XEmbed(_) => {}
+        }
+        self.update_xembed_icon();
+    }
+
+    pub fn set_status(&self, status: Status) {
+        self.status.set(status);
+        self.apply_status();
+        self.update_xembed_icon();
     }
 
-    pub fn set_icon_from_file(&self, file: &String) {
-        let mut ai = self.ai.borrow_mut();
-        ai.set_icon_full(file, "icon");
+    pub fn show(&self) {
+        self.shown.set(true);
+        self.apply_status();
+    }
+
+    // AppIndicator items start out passive and GtkStatusIcons hidden, they
+    // keep that until shown.
+    fn apply_status(&self) {
+        if !self.shown.get() {
+            return;
+        }
+        let status = self.status.get();
+        match *self.tray.borrow_mut() {
+            Tray::AppIndicator(ref mut ai) => ai.set_status(match status {
+                Status::Active => AppIndicatorStatus::APP_INDICATOR_STATUS_ACTIVE,
+                Status::Passive => AppIndicatorStatus::APP_INDICATOR_STATUS_PASSIVE,
+                Status::NeedsAttention => AppIndicatorStatus::APP_INDICATOR_STATUS_ATTENTION,
+            }),
+            // Tray managers have no notion of a passive item, hide it instead.
+            Tray::XEmbed(ref icon) => icon.set_visible(status != Status::Passive),
+        }
+    }
+
+    fn update_xembed_icon(&self) {
+        if let Tray::XEmbed(ref icon) = *self.tray.borrow() {
+            let attention = self.attention_icon.borrow();
+            let normal = self.icon.borrow();
+            let file = match (self.status.get(), attention.as_ref()) {
+                (Status::NeedsAttention, Some(f)) => Some(f),
+                _ => normal.as_ref(),
+            };
+            if let Some(f) = file {
+                icon.set_from_file(f);
+            }
+        }
+    }
+
+    // Bring a new StatusNotifierHost up to date after a panel restart.
+    // libappindicator registers with the new watcher by itself, re-applying
+    // the status makes sure the host picks up the item.
+    pub fn reregister(&self) {
+        let indicator = match *self.tray.borrow() {
+            Tray::AppIndicator(_) => true,
+            Tray::XEmbed(_) => false,
+        };
+        if indicator {
+            self.apply_status();
+        }
+    }
+
+    pub fn set_tooltip(&self, tooltip: &str) {
+        if let Tray::XEmbed(ref icon) = *self.tray.borrow() {
+            icon.set_tooltip_markup(tooltip);
+        }
+    }
+
+    pub fn set_tool_tip(&self, tool_tip: sni::ToolTip) {
+        if let Tray::AppIndicator(ref mut ai) = *self.tray.borrow_mut() {
+            ai.set_tool_tip(tool_tip);
+        }
+    }
+
+    // Takes the icon off the panel. The GTK thread keeps running for the
+    // other trays.
+    fn remove(&self) {
+        match *self.tray.borrow_mut() {
+            Tray::AppIndicator(ref mut ai) => {
+                ai.set_status(AppIndicatorStatus::APP_INDICATOR_STATUS_PASSIVE);
+                ai.remove_tool_tip();
+            }
+            Tray::XEmbed(ref icon) => icon.set_visible(false),
+        }
     }
 }
 
 pub struct Window {
-    gtk_loop: Option<thread::JoinHandle<()>>,
+    tray: TrayId,
+    backend: Backend,
+    watching: Arc<AtomicBool>,
+    event_tx: Sender<Event>,
+    // Connected on first use, so that a tray without notifications doesn't
+    // need a notification server.
+    notifier: RefCell<Option<Notifier>>,
+    removed: bool,
+}
+
+// Name shown by notification servers, taken from the executable.
+fn app_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .unwrap_or_default()
 }
 
 impl Window {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<Window, SystrayError> {
+    pub(crate) fn new(
+        tray: TrayId,
+        options: &TrayOptions,
+        event_tx: Sender<Event>,
+    ) -> Result<Window, SystrayError> {
+        let backend = match Backend::from_env() {
+            Some(b) => b,
+            None => Backend::detect()?,
+        };
+        start_gtk_thread()?;
         let (tx, rx) = channel();
-        let gtk_loop = thread::spawn(move || {
-            GTK_STASH.with(|stash| match GtkSystrayApp::new(event_tx) {
-                Ok(data) => {
-                    (*stash.borrow_mut()) = Some(data);
-                    tx.send(Ok(()));
-                }
-                Err(e) => {
-                    tx.send(Err(e));
-                    return;
+        let gtk_event_tx = event_tx.clone();
+        let options = options.clone();
+        glib::idle_add(move || {
+            let created =
+                GtkSystrayApp::new(tray, &options, gtk_event_tx.clone(), backend).map(|data| {
+                    GTK_STASH.with(|stash| stash.borrow_mut().insert(tray, data));
+                });
+            tx.send(created).ok();
+            glib::Continue(false)
+        });
+        wait_for_gtk_thread(rx)??;
+        // Only watched once the tray is up, so that failing above leaves
+        // nothing running.
+        let watching = Arc::new(AtomicBool::new(backend == Backend::AppIndicator));
+        if backend == Backend::AppIndicator {
+            let watch = watcher::watch(watching.clone(), move |present| {
+                if present {
+                    info!("StatusNotifierWatcher appeared, registering the indicator again");
+                    run_on_gtk_thread(tray, |stash: &GtkSystrayApp| stash.reregister());
+                } else {
+                    warn!("StatusNotifierWatcher went away, the indicator is hidden until a panel comes back");
                 }
             });
-            gtk::main();
-        });
-        match rx.recv().unwrap() {
-            Ok(()) => Ok(Window {
-                gtk_loop: Some(gtk_loop),
-            }),
-            Err(e) => Err(e),
+            if let Err(e) = watch {
+                warn!("Can't watch for StatusNotifierWatcher restarts: {}", e);
+            }
         }
+        Ok(Window {
+            tray,
+            backend,
+            watching,
+            event_tx,
+            notifier: RefCell::new(None),
+            removed: false,
+        })
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        match self.backend {
+            // AppIndicator takes the menu as a whole, so nothing beyond menu
+            // entries, check items, status, notifications and the tooltip of
+            // the item is available.
+            Backend::AppIndicator => Capabilities {
+                tooltips: true,
+                markup_tooltips: true,
+                check_items: true,
+                status: true,
+                notifications: true,
+                ..Default::default()
+            },
+            Backend::XEmbed => Capabilities {
+                tooltips: true,
+                item_tooltips: true,
+                markup_tooltips: true,
+                check_items: true,
+                menu_lifecycle_events: true,
+                status: true,
+                notifications: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn add_menu_entry(
+        &self,
+        submenu: u64,
+        menu_idx: u32,
+        _item_idx: u32,
+        label: &Label,
+        _icon: Option<Icon>,
+        checked: Option<bool>,
+    ) -> Result<(), SystrayError> {
+        let n = label.clone();
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.add_menu_entry(submenu, menu_idx, &n, checked);
+        });
+        Ok(())
     }
 
-    pub fn add_menu_entry(&self, item_idx: u32, item_name: &String) -> Result<(), SystrayError> {
-        let n = item_name.clone();
-        run_on_gtk_thread(move |stash: &GtkSystrayApp| {
-            stash.add_menu_entry(item_idx, &n);
+    pub fn add_menu_group(
+        &self,
+        submenu: u64,
+        menu_idx: u32,
+        _item_idx: u32,
+        label: &Label,
+        _icon: Option<Icon>,
+    ) -> Result<u64, SystrayError> {
+        // Menu 0 is the root menu, so shift group ids by one.
+        let group = u64::from(menu_idx) + 1;
+        let n = label.clone();
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.add_menu_group(submenu, menu_idx, &n, group);
+        });
+        Ok(group)
+    }
+
+    pub fn add_dynamic_submenu(
+        &self,
+        submenu: u64,
+        menu_idx: u32,
+        _item_idx: u32,
+        label: &Label,
+        populate: Populate,
+        ids: Arc<AtomicU32>,
+    ) -> Result<u64, SystrayError> {
+        let group = u64::from(menu_idx) + 1;
+        let n = label.clone();
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.add_dynamic_submenu(submenu, menu_idx, &n, group, populate.clone(), ids.clone());
+        });
+        Ok(group)
+    }
+
+    pub fn add_menu_separator(
+        &self,
+        submenu: u64,
+        _menu_idx: u32,
+        _item_idx: u32,
+    ) -> Result<(), SystrayError> {
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.add_menu_separator(submenu);
         });
         Ok(())
     }
 
-    pub fn add_menu_separator(&self, item_idx: u32) -> Result<(), SystrayError> {
-        run_on_gtk_thread(move |stash: &GtkSystrayApp| {
-            stash.add_menu_separator(item_idx);
+    pub fn set_menu_item_tooltip(&self, item: u32, tooltip: &str) -> Result<(), SystrayError> {
+        let n = tooltip.to_owned();
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.set_menu_item_tooltip(item, &n);
         });
         Ok(())
     }
 
-    pub fn set_icon_from_file(&self, file: &String) -> Result<(), SystrayError> {
-        let n: String = file.clone();
-        run_on_gtk_thread(move |stash: &GtkSystrayApp| {
+    pub fn set_menu_item_checked(&self, item: u32, checked: bool) -> Result<(), SystrayError> {
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.set_menu_item_checked(item, checked);
+        });
+        Ok(())
+    }
+
+    pub fn set_icon_from_file(&self, file: &str) -> Result<(), SystrayError> {
+        check_icon_file(file)?;
+        let n = file.to_owned();
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
             stash.set_icon_from_file(&n);
         });
         Ok(())
     }
 
-    pub fn set_icon_from_resource(&self, resource: &str) -> Result<(), SystrayError> {
-        panic!("Not implemented on this platform!");
+    pub fn set_attention_icon_from_file(&self, file: &str) -> Result<(), SystrayError> {
+        check_icon_file(file)?;
+        let n = file.to_owned();
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.set_attention_icon_from_file(&n);
+        });
+        Ok(())
+    }
+
+    pub fn set_status(&self, status: Status) -> Result<(), SystrayError> {
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.set_status(status);
+        });
+        Ok(())
     }
 
-    pub fn shutdown(&self) -> Result<(), SystrayError> {
+    pub fn show(&self) -> Result<(), SystrayError> {
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.show();
+        });
         Ok(())
     }
 
-    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError> {
-        panic!("Not implemented on this platform!");
+    pub fn set_icon_from_resource(&self, _resource: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::Unsupported(Capability::ResourceIcons))
+    }
+
+    pub fn set_tooltip(&self, tooltip: &Tooltip) -> Result<TooltipFit, SystrayError> {
+        if self.backend == Backend::AppIndicator {
+            let tool_tip = sni::ToolTip {
+                icon: tooltip.icon.clone().unwrap_or_default(),
+                title: tooltip.title.clone(),
+                description: tooltip.body_markup().to_sni_markup(),
+            };
+            run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+                stash.set_tool_tip(tool_tip.clone());
+            });
+            return Ok(TooltipFit::Whole);
+        }
+        // GtkStatusIcon tooltips take Pango markup, show the title in bold.
+        let title = Markup::text(&tooltip.title).to_pango_markup();
+        let body = tooltip.body_markup().to_pango_markup();
+        let n = match (title.is_empty(), body.is_empty()) {
+            (_, true) => format!("<b>{}</b>", title),
+            (true, false) => body,
+            (false, false) => format!("<b>{}</b>\n{}", title, body),
+        };
+        run_on_gtk_thread(self.tray, move |stash: &GtkSystrayApp| {
+            stash.set_tooltip(&n);
+        });
+        Ok(TooltipFit::Whole)
     }
 
-    pub fn quit(&self) {
-        glib::idle_add(|| {
-            gtk::main_quit();
+    pub fn notify(&self, notification: &Notification) -> Result<u32, SystrayError> {
+        let mut notifier = self.notifier.borrow_mut();
+        if notifier.is_none() {
+            let n = Notifier::new(app_name(), self.event_tx.clone()).map_err(SystrayError::dbus)?;
+            *notifier = Some(n);
+        }
+        notifier.as_ref().unwrap().notify(notification)
+    }
+
+    // Removes the icon and waits until the GTK thread did so. The thread
+    // itself keeps running for the other trays. Does nothing once the icon
+    // is gone.
+    pub fn quit(&mut self) -> Result<(), SystrayError> {
+        if self.removed {
+            return Ok(());
+        }
+        self.removed = true;
+        self.watching.store(false, Ordering::SeqCst);
+        check_gtk_thread()?;
+        let (tx, rx) = channel();
+        let tray = self.tray;
+        glib::idle_add(move || {
+            GTK_STASH.with(|stash| {
+                if let Some(app) = stash.borrow_mut().remove(&tray) {
+                    app.remove();
+                }
+            });
+            tx.send(()).ok();
             glib::Continue(false)
         });
+        wait_for_gtk_thread(rx)
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        self.quit().ok();
     }
 }
@@ -0,0 +1,146 @@
+// Desktop notifications through org.freedesktop.Notifications.
+//
+// Some servers only send ActionInvoked and NotificationClosed back to the
+// connection that called Notify, so a single connection does both, owned by
+// a thread that also forwards the signals to the Application.
+use crate::{CloseReason, Event, Notification, NotificationEvent, SystrayError, Urgency};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+type Request = (Notification, Sender<Result<u32, dbus::Error>>);
+
+pub struct Notifier {
+    requests: Sender<Request>,
+}
+
+fn close_reason(code: u32) -> CloseReason {
+    match code {
+        1 => CloseReason::Expired,
+        2 => CloseReason::Dismissed,
+        3 => CloseReason::Closed,
+        _ => CloseReason::Undefined,
+    }
+}
+
+fn send_notification(
+    conn: &Connection,
+    app_name: &str,
+    notification: &Notification,
+) -> Result<u32, dbus::Error> {
+    let mut actions = Vec::new();
+    for (key, label) in &notification.actions {
+        actions.push(key.as_str());
+        actions.push(label.as_str());
+    }
+    let urgency: u8 = match notification.urgency {
+        Urgency::Low => 0,
+        Urgency::Normal => 1,
+        Urgency::Critical => 2,
+    };
+    let mut hints: PropMap = HashMap::new();
+    hints.insert(
+        "urgency".to_string(),
+        Variant(Box::new(urgency) as Box<dyn RefArg>),
+    );
+    let timeout = match notification.timeout {
+        Some(t) => t.as_millis().min(i32::MAX as u128) as i32,
+        None => -1,
+    };
+    let proxy = conn.with_proxy(NOTIFICATIONS_NAME, NOTIFICATIONS_PATH, TIMEOUT);
+    let (id,): (u32,) = proxy.method_call(
+        NOTIFICATIONS_NAME,
+        "Notify",
+        (
+            app_name,
+            0u32,
+            notification.icon.as_ref().map_or("", String::as_str),
+            notification.summary.as_str(),
+            notification.body.as_str(),
+            actions,
+            hints,
+            timeout,
+        ),
+    )?;
+    Ok(id)
+}
+
+impl Notifier {
+    pub fn new(app_name: String, event_tx: Sender<Event>) -> Result<Notifier, dbus::Error> {
+        let conn = Connection::new_session()?;
+        // Other applications' notifications are signalled too, only forward
+        // ours.
+        let sent = Arc::new(Mutex::new(HashSet::new()));
+
+        let (ids, tx) = (sent.clone(), event_tx.clone());
+        conn.add_match(
+            MatchRule::new_signal(NOTIFICATIONS_NAME, "ActionInvoked"),
+            move |(id, action): (u32, String), _, _| {
+                if ids.lock().unwrap().contains(&id) {
+                    tx.send(Event::Notification(NotificationEvent::ActionInvoked {
+                        id,
+                        action,
+                    }))
+                    .ok();
+                }
+                true
+            },
+        )?;
+        let (ids, tx) = (sent.clone(), event_tx);
+        conn.add_match(
+            MatchRule::new_signal(NOTIFICATIONS_NAME, "NotificationClosed"),
+            move |(id, reason): (u32, u32), _, _| {
+                if ids.lock().unwrap().remove(&id) {
+                    tx.send(Event::Notification(NotificationEvent::Closed {
+                        id,
+                        reason: close_reason(reason),
+                    }))
+                    .ok();
+                }
+                true
+            },
+        )?;
+
+        let (requests, rx) = channel::<Request>();
+        thread::spawn(move || loop {
+            match rx.try_recv() {
+                Ok((notification, reply)) => {
+                    let result = send_notification(&conn, &app_name, &notification);
+                    if let Ok(id) = result {
+                        sent.lock().unwrap().insert(id);
+                    }
+                    reply.send(result).ok();
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => {}
+            }
+            if let Err(e) = conn.process(Duration::from_millis(100)) {
+                warn!("Stopped listening for notification signals: {}", e);
+                break;
+            }
+        });
+        Ok(Notifier { requests })
+    }
+
+    pub fn notify(&self, notification: &Notification) -> Result<u32, SystrayError> {
+        let (tx, rx) = channel();
+        self.requests
+            .send((notification.clone(), tx))
+            .map_err(|_| SystrayError::ThreadGone)?;
+        match rx.recv() {
+            Ok(Ok(id)) => Ok(id),
+            Ok(Err(e)) => Err(SystrayError::dbus(e)),
+            Err(_) => Err(SystrayError::ThreadGone),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// The ToolTip property of StatusNotifierItems, which libappindicator doesn't
+// export.
+//
+// libappindicator serves its items on the shared session connection of GDBus,
+// so a message filter on that connection can answer for it: calls reading the
+// tooltip of one of our items are noted on their way in, and libappindicator's
+// replies to them, an error for `Get` and the properties without the tooltip
+// for `GetAll`, are swapped for ones with the tooltip on their way out.
+use gio_sys::{
+    g_bus_get_sync, g_dbus_connection_add_filter, g_dbus_connection_emit_signal,
+    g_dbus_message_copy, g_dbus_message_get_arg0, g_dbus_message_get_body,
+    g_dbus_message_get_destination, g_dbus_message_get_interface, g_dbus_message_get_member,
+    g_dbus_message_get_message_type, g_dbus_message_get_path, g_dbus_message_get_reply_serial,
+    g_dbus_message_get_sender, g_dbus_message_get_serial, g_dbus_message_get_signature,
+    g_dbus_message_set_body, g_dbus_message_set_header, g_dbus_message_set_message_type,
+    GDBusConnection, GDBusMessage, G_BUS_TYPE_SESSION, G_DBUS_MESSAGE_HEADER_FIELD_ERROR_NAME,
+    G_DBUS_MESSAGE_TYPE_ERROR, G_DBUS_MESSAGE_TYPE_METHOD_CALL, G_DBUS_MESSAGE_TYPE_METHOD_RETURN,
+};
+use glib_sys::{
+    g_error_free, g_variant_builder_add_value, g_variant_builder_end, g_variant_builder_new,
+    g_variant_builder_unref, g_variant_get_child_value, g_variant_get_string, g_variant_n_children,
+    g_variant_new_array, g_variant_new_dict_entry, g_variant_new_string, g_variant_new_tuple,
+    g_variant_new_variant, g_variant_ref_sink, g_variant_unref, gboolean, gpointer, GError,
+    GVariant, GVariantType, GFALSE,
+};
+use gobject_sys::{g_object_unref, GObject};
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, Once};
+
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// What the ToolTip property holds. Its pixmaps are always left empty, hosts
+/// look the icon up by name, which can also be the path of a file.
+#[derive(Clone, Debug, Default)]
+pub struct ToolTip {
+    pub icon: String,
+    pub title: String,
+    /// May contain the markup of `Markup::to_sni_markup`.
+    pub description: String,
+}
+
+// A call reading the tooltip, until libappindicator answers it.
+struct PendingCall {
+    sender: String,
+    serial: u32,
+    path: String,
+    // GetAll rather than Get.
+    all: bool,
+}
+
+// Tooltips by object path of their item.
+static TOOL_TIPS: Mutex<Vec<(String, ToolTip)>> = Mutex::new(Vec::new());
+static PENDING: Mutex<Vec<PendingCall>> = Mutex::new(Vec::new());
+// The session connection the filter is on, kept for the life of the process.
+static CONNECTION: AtomicPtr<GDBusConnection> = AtomicPtr::new(ptr::null_mut());
+static FILTER: Once = Once::new();
+
+/// The object path libappindicator exports the item with id `id` at, with
+/// every byte that isn't an ASCII letter or digit replaced, like it does.
+pub fn item_path(id: &str) -> String {
+    let clean: String = id
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() {
+                b as char
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("/org/ayatana/NotificationItem/{}", clean)
+}
+
+/// Publishes `tool_tip` as the ToolTip of the item at `path`.
+pub fn set_tool_tip(path: &str, tool_tip: ToolTip) {
+    FILTER.call_once(|| unsafe { add_filter() });
+    {
+        let mut tool_tips = TOOL_TIPS.lock().unwrap();
+        match tool_tips.iter_mut().find(|(p, _)| p == path) {
+            Some(entry) => entry.1 = tool_tip,
+            None => tool_tips.push((path.to_owned(), tool_tip)),
+        }
+    }
+    unsafe { emit_new_tool_tip(path) };
+}
+
+/// Stops answering for the item at `path`, once it is gone.
+pub fn remove(path: &str) {
+    TOOL_TIPS.lock().unwrap().retain(|(p, _)| p != path);
+}
+
+unsafe fn take_error(error: *mut GError) -> String {
+    if error.is_null() {
+        return String::new();
+    }
+    let message = CStr::from_ptr((*error).message)
+        .to_string_lossy()
+        .into_owned();
+    g_error_free(error);
+    message
+}
+
+unsafe fn add_filter() {
+    let mut error = ptr::null_mut();
+    let connection = g_bus_get_sync(G_BUS_TYPE_SESSION, ptr::null_mut(), &mut error);
+    if connection.is_null() {
+        warn!(
+            "Can't connect to the session bus, tooltips stay hidden: {}",
+            take_error(error)
+        );
+        return;
+    }
+    g_dbus_connection_add_filter(connection, Some(filter), ptr::null_mut(), None);
+    CONNECTION.store(connection, Ordering::SeqCst);
+}
+
+unsafe fn emit_new_tool_tip(path: &str) {
+    let connection = CONNECTION.load(Ordering::SeqCst);
+    if connection.is_null() {
+        return;
+    }
+    let path = CString::new(path).unwrap_or_default();
+    let interface = CString::new(ITEM_INTERFACE).unwrap();
+    let mut error = ptr::null_mut();
+    let emitted = g_dbus_connection_emit_signal(
+        connection,
+        ptr::null(),
+        path.as_ptr(),
+        interface.as_ptr(),
+        b"NewToolTip\0".as_ptr() as *const c_char,
+        ptr::null_mut(),
+        &mut error,
+    );
+    if emitted == GFALSE {
+        warn!("Can't announce the new tooltip: {}", take_error(error));
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
+
+// Runs on the worker thread of GDBus, for every message of the connection.
+// It owns `message` and returns what goes on in its place.
+unsafe extern "C" fn filter(
+    _: *mut GDBusConnection,
+    message: *mut GDBusMessage,
+    incoming: gboolean,
+    _: gpointer,
+) -> *mut GDBusMessage {
+    if incoming != GFALSE {
+        note_call(message);
+        message
+    } else {
+        replace_reply(message)
+    }
+}
+
+unsafe fn note_call(message: *mut GDBusMessage) {
+    if g_dbus_message_get_message_type(message) != G_DBUS_MESSAGE_TYPE_METHOD_CALL
+        || to_str(g_dbus_message_get_interface(message)) != Some(PROPERTIES_INTERFACE)
+        || to_str(g_dbus_message_get_arg0(message)) != Some(ITEM_INTERFACE)
+    {
+        return;
+    }
+    let all = match to_str(g_dbus_message_get_member(message)) {
+        Some("GetAll") => true,
+        Some("Get") if property_name(message).as_deref() == Some("ToolTip") => false,
+        _ => return,
+    };
+    let (path, sender) = match (
+        to_str(g_dbus_message_get_path(message)),
+        to_str(g_dbus_message_get_sender(message)),
+    ) {
+        (Some(path), Some(sender)) => (path, sender),
+        _ => return,
+    };
+    let ours = match TOOL_TIPS.lock() {
+        Ok(tool_tips) => tool_tips.iter().any(|(p, _)| p == path),
+        Err(_) => false,
+    };
+    if let (true, Ok(mut pending)) = (ours, PENDING.lock()) {
+        pending.push(PendingCall {
+            sender: sender.to_owned(),
+            serial: g_dbus_message_get_serial(message),
+            path: path.to_owned(),
+            all,
+        });
+    }
+}
+
+// The second argument of a `Get(ss)` call.
+unsafe fn property_name(message: *mut GDBusMessage) -> Option<String> {
+    if to_str(g_dbus_message_get_signature(message)) != Some("ss") {
+        return None;
+    }
+    let name = g_variant_get_child_value(g_dbus_message_get_body(message), 1);
+    let s = to_str(g_variant_get_string(name, ptr::null_mut())).map(str::to_owned);
+    g_variant_unref(name);
+    s
+}
+
+unsafe fn replace_reply(message: *mut GDBusMessage) -> *mut GDBusMessage {
+    let kind = g_dbus_message_get_message_type(message);
+    if kind != G_DBUS_MESSAGE_TYPE_METHOD_RETURN && kind != G_DBUS_MESSAGE_TYPE_ERROR {
+        return message;
+    }
+    let destination = match to_str(g_dbus_message_get_destination(message)) {
+        Some(destination) => destination,
+        None => return message,
+    };
+    let serial = g_dbus_message_get_reply_serial(message);
+    let call = match PENDING.lock() {
+        Ok(mut pending) => {
+            match pending
+                .iter()
+                .position(|c| c.serial == serial && c.sender == destination)
+            {
+                Some(i) => pending.swap_remove(i),
+                None => return message,
+            }
+        }
+        Err(_) => return message,
+    };
+    let tool_tip = match TOOL_TIPS.lock() {
+        Ok(tool_tips) => match tool_tips.iter().find(|(p, _)| *p == call.path) {
+            Some((_, tool_tip)) => tool_tip.clone(),
+            None => return message,
+        },
+        Err(_) => return message,
+    };
+    let body = if call.all {
+        // A failed GetAll is passed on as it is.
+        if kind != G_DBUS_MESSAGE_TYPE_METHOD_RETURN {
+            return message;
+        }
+        with_tool_tip(g_dbus_message_get_body(message), &tool_tip)
+    } else {
+        let mut value = g_variant_new_variant(tool_tip_variant(&tool_tip));
+        g_variant_new_tuple(&mut value, 1)
+    };
+    // Messages are locked once sent, the copy keeps the serial.
+    let mut error = ptr::null_mut();
+    let reply = g_dbus_message_copy(message, &mut error);
+    if reply.is_null() {
+        warn!("Can't answer for the tooltip: {}", take_error(error));
+        g_variant_unref(g_variant_ref_sink(body));
+        return message;
+    }
+    g_object_unref(message as *mut GObject);
+    g_dbus_message_set_message_type(reply, G_DBUS_MESSAGE_TYPE_METHOD_RETURN);
+    g_dbus_message_set_header(
+        reply,
+        G_DBUS_MESSAGE_HEADER_FIELD_ERROR_NAME,
+        ptr::null_mut(),
+    );
+    g_dbus_message_set_body(reply, body);
+    reply
+}
+
+unsafe fn new_string(s: &str) -> *mut GVariant {
+    let s = CString::new(s.replace('\0', "")).unwrap_or_default();
+    g_variant_new_string(s.as_ptr())
+}
+
+// The `(sa(iiay)ss)` value of the property.
+unsafe fn tool_tip_variant(tool_tip: &ToolTip) -> *mut GVariant {
+    let pixmaps = g_variant_new_array(
+        b"(iiay)\0".as_ptr() as *const GVariantType,
+        ptr::null_mut(),
+        0,
+    );
+    let mut fields = [
+        new_string(&tool_tip.icon),
+        pixmaps,
+        new_string(&tool_tip.title),
+        new_string(&tool_tip.description),
+    ];
+    g_variant_new_tuple(fields.as_mut_ptr(), fields.len())
+}
+
+// The `(a{sv})` body of a GetAll reply, with the tooltip added.
+unsafe fn with_tool_tip(body: *mut GVariant, tool_tip: &ToolTip) -> *mut GVariant {
+    let builder = g_variant_builder_new(b"a{sv}\0".as_ptr() as *const GVariantType);
+    if !body.is_null() && g_variant_n_children(body) == 1 {
+        let properties = g_variant_get_child_value(body, 0);
+        for i in 0..g_variant_n_children(properties) {
+            let entry = g_variant_get_child_value(properties, i);
+            g_variant_builder_add_value(builder, entry);
+            g_variant_unref(entry);
+        }
+        g_variant_unref(properties);
+    }
+    let entry = g_variant_new_dict_entry(
+        new_string("ToolTip"),
+        g_variant_new_variant(tool_tip_variant(tool_tip)),
+    );
+    g_variant_builder_add_value(builder, entry);
+    let mut properties = g_variant_builder_end(builder);
+    g_variant_builder_unref(builder);
+    g_variant_new_tuple(&mut properties, 1)
+}
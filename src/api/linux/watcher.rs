@@ -0,0 +1,57 @@
+// AppIndicator icons are only displayed if a panel has registered itself as a
+// StatusNotifierHost with the StatusNotifierWatcher. Without one,
+// libappindicator happily creates an indicator that nobody ever shows, so
+// check for a host up front and keep an eye on the watcher afterwards.
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+pub fn host_registered() -> Result<bool, dbus::Error> {
+    let conn = Connection::new_session()?;
+    let bus = conn.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", TIMEOUT);
+    let (has_owner,): (bool,) =
+        bus.method_call("org.freedesktop.DBus", "NameHasOwner", (WATCHER_NAME,))?;
+    if !has_owner {
+        return Ok(false);
+    }
+    let watcher = conn.with_proxy(WATCHER_NAME, WATCHER_PATH, TIMEOUT);
+    watcher.get(WATCHER_NAME, "IsStatusNotifierHostRegistered")
+}
+
+// Calls `on_change(true)` whenever a new watcher takes over the bus name (a
+// panel restarted) and `on_change(false)` when it goes away, until `running`
+// is cleared.
+pub fn watch<F>(running: Arc<AtomicBool>, on_change: F) -> Result<(), dbus::Error>
+where
+    F: Fn(bool) + Send + 'static,
+{
+    let conn = Connection::new_session()?;
+    let rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+        .with_sender("org.freedesktop.DBus");
+    conn.add_match(
+        rule,
+        move |(name, _old, new): (String, String, String), _, _| {
+            if name == WATCHER_NAME {
+                on_change(!new.is_empty());
+            }
+            true
+        },
+    )?;
+    thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            if let Err(e) = conn.process(Duration::from_secs(1)) {
+                warn!("Stopped watching {}: {}", WATCHER_NAME, e);
+                break;
+            }
+        }
+    });
+    Ok(())
+}
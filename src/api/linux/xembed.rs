@@ -0,0 +1,27 @@
+// XEmbed system tray backend, for X11 sessions that have a tray manager
+// (stalonetray, trayer, the bars of i3 or xmonad, ...) but no
+// StatusNotifierHost, and therefore never show AppIndicator icons.
+//
+// GtkStatusIcon already speaks the freedesktop System Tray protocol: it
+// looks up the owner of the _NET_SYSTEM_TRAY_Sn selection, sends it a
+// SYSTEM_TRAY_REQUEST_DOCK client message and paints the icon into the
+// window the tray manager embeds. Tray managers don't draw menus, so we pop
+// up our own.
+use gtk::{self, MenuExtManual, StatusIconExt};
+
+pub fn new_status_icon(menu: &gtk::Menu) -> gtk::StatusIcon {
+    let icon = gtk::StatusIcon::new();
+    // Left click is "activate", right click is "popup-menu". Open the menu on
+    // both, like the win32 backend does.
+    let m = menu.clone();
+    icon.connect_activate(move |_| {
+        m.popup_easy(1, gtk::get_current_event_time());
+    });
+    let m = menu.clone();
+    icon.connect_popup_menu(move |_, button, time| {
+        m.popup_easy(button, time);
+    });
+    // Shown once the tray is set up.
+    icon.set_visible(false);
+    icon
+}
@@ -1,37 +1,56 @@
-use crate::{Icon, SystrayError, SystrayEvent};
+use crate::builder::TrayOptions;
+use crate::menu_builder::{BuilderItem, MenuBuilder, Populate};
+use crate::tooltip::copy_utf16;
+use crate::{
+    Capabilities, Capability, CloseReason, ErrorChain, Event, Icon, Label, MenuLifecycleEvent,
+    Notification, NotificationEvent, Status, SystrayError, SystrayEvent, Tooltip, TooltipFit,
+    TrayId, Urgency,
+};
 use std;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::io;
 use std::os::windows::ffi::OsStrExt;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 use winapi::ctypes::{c_int, c_ulong, c_ushort, c_void};
 use winapi::shared::basetsd::ULONG_PTR;
 use winapi::shared::guiddef::GUID;
-use winapi::shared::minwindef::{DWORD, HINSTANCE, LPARAM, LRESULT, PBYTE, TRUE, UINT, WPARAM};
+use winapi::shared::minwindef::{
+    DWORD, FALSE, HINSTANCE, LPARAM, LRESULT, PBYTE, TRUE, UINT, WPARAM,
+};
 use winapi::shared::windef::{HBITMAP, HBRUSH, HICON, HMENU, HWND, POINT, RECT};
+use winapi::shared::winerror::ERROR_CLASS_ALREADY_EXISTS;
 use winapi::um::errhandlingapi::GetLastError;
 use winapi::um::libloaderapi::GetModuleHandleA;
 use winapi::um::shellapi::{
-    Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NIM_MODIFY,
-    NOTIFYICONDATAW,
+    Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_STATE, NIF_TIP, NIIF_ERROR, NIIF_INFO,
+    NIIF_LARGE_ICON, NIIF_NONE, NIIF_USER, NIM_ADD, NIM_DELETE, NIM_MODIFY, NIN_BALLOONHIDE,
+    NIN_BALLOONTIMEOUT, NIN_BALLOONUSERCLICK, NIS_HIDDEN, NOTIFYICONDATAW,
 };
 use winapi::um::wingdi::{CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, SelectObject};
 use winapi::um::winnt::LPCWSTR;
 use winapi::um::winuser::{
-    CreateIconFromResourceEx, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyIcon,
-    DispatchMessageW, DrawIconEx, FillRect, GetCursorPos, GetDC, GetMenuItemID, GetMessageW,
-    InsertMenuItemW, LoadCursorW, LoadIconW, LoadImageW, LookupIconIdFromDirectoryEx, PostMessageW,
-    PostQuitMessage, RegisterClassW, ReleaseDC, SetForegroundWindow, SetMenuInfo, TrackPopupMenu,
+    CreateIconFromResourceEx, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DeleteMenu,
+    DestroyIcon, DispatchMessageW, DrawIconEx, FillRect, GetCursorPos, GetDC, GetMenuItemCount,
+    GetMenuItemID, GetMenuItemInfoW, GetMessageW, InsertMenuItemW, LoadCursorW, LoadIconW,
+    LoadImageW, LookupIconIdFromDirectoryEx, PostMessageW, PostQuitMessage, RegisterClassW,
+    ReleaseDC, SetForegroundWindow, SetMenuInfo, SetMenuItemInfoW, TrackPopupMenu,
     TranslateMessage, CW_USEDEFAULT, IDI_APPLICATION, IMAGE_ICON, LR_DEFAULTCOLOR, LR_LOADFROMFILE,
-    MENUINFO, MENUITEMINFOW, MFT_SEPARATOR, MFT_STRING, MIIM_BITMAP, MIIM_FTYPE, MIIM_ID,
-    MIIM_STATE, MIIM_STRING, MIIM_SUBMENU, MIM_APPLYTOSUBMENUS, MIM_STYLE, MNS_NOTIFYBYPOS, MSG,
-    TPM_BOTTOMALIGN, TPM_LEFTALIGN, WM_DESTROY, WM_LBUTTONUP, WM_MENUCOMMAND, WM_QUIT,
-    WM_RBUTTONUP, WM_USER, WNDCLASSW, WS_OVERLAPPEDWINDOW,
+    MENUINFO, MENUITEMINFOW, MFS_CHECKED, MFS_UNCHECKED, MFT_SEPARATOR, MFT_STRING, MF_BYPOSITION,
+    MIIM_BITMAP, MIIM_DATA, MIIM_FTYPE, MIIM_ID, MIIM_STATE, MIIM_STRING, MIIM_SUBMENU,
+    MIM_APPLYTOSUBMENUS, MIM_STYLE, MNS_NOTIFYBYPOS, MSG, TPM_BOTTOMALIGN, TPM_LEFTALIGN,
+    WM_DESTROY, WM_INITMENUPOPUP, WM_LBUTTONUP, WM_MENUCOMMAND, WM_QUIT, WM_RBUTTONUP,
+    WM_UNINITMENUPOPUP, WM_USER, WNDCLASSW, WS_OVERLAPPEDWINDOW,
 };
 
 // Got this idea from glutin. Yay open source! Boo stupid winproc! Even more boo
-// doing SetLongPtr tho.
+// doing SetLongPtr tho. Every icon runs its own loop thread, so each gets its
+// own stash.
 thread_local!(static WININFO_STASH: RefCell<Option<WindowsLoopData>> = RefCell::new(None));
 
 fn to_wstring(str: &str) -> Vec<u16> {
@@ -41,6 +60,15 @@ fn to_wstring(str: &str) -> Vec<u16> {
         .collect::<Vec<_>>()
 }
 
+// Win32 menus right align whatever follows a tab, which is where the
+// accelerator goes.
+fn win32_label(label: &Label) -> String {
+    match label.accelerator {
+        Some(ref accelerator) => format!("{}\t{}", label.text.to_win32_text(), accelerator),
+        None => label.text.to_win32_text(),
+    }
+}
+
 const ICON_SIZE: i32 = 16;
 
 pub type IconHandle = HICON;
@@ -48,6 +76,8 @@ pub type IconHandle = HICON;
 #[derive(Clone)]
 struct WindowInfo {
     pub hwnd: HWND,
+    // Id of the icon in the notification area, from its TrayId.
+    pub uid: UINT,
     pub hinstance: HINSTANCE,
     pub hmenu: HMENU,
 }
@@ -58,11 +88,104 @@ unsafe impl Sync for WindowInfo {}
 #[derive(Clone)]
 struct WindowsLoopData {
     pub info: WindowInfo,
-    pub tx: Sender<SystrayEvent>,
+    pub tx: Sender<Event>,
+    // Id of the last balloon notification, Windows shows one at a time.
+    pub balloon: Arc<AtomicU32>,
+    pub dynamic: DynamicMenus,
 }
 
+// Dynamic submenus by HMENU, with the counter their item ids come from.
+type DynamicMenus = Arc<Mutex<HashMap<u64, (Populate, Arc<AtomicU32>)>>>;
+
 unsafe fn get_win_os_error(msg: &str) -> SystrayError {
-    SystrayError::OsError(format!("{}: {}", &msg, GetLastError()))
+    SystrayError::Os {
+        context: msg.to_string(),
+        source: io::Error::from_raw_os_error(GetLastError() as i32),
+    }
+}
+
+unsafe fn get_icon_load_error(path: &str) -> SystrayError {
+    SystrayError::IconLoad {
+        path: path.to_string(),
+        source: io::Error::from_raw_os_error(GetLastError() as i32),
+    }
+}
+
+// Check items carry this in dwItemData, so that clicks know to toggle them.
+const CHECK_ITEM: ULONG_PTR = 1;
+
+// Flips the check mark of the item at `pos` if it is a check item, and
+// returns its new state.
+unsafe fn toggle_check_item(hmenu: HMENU, pos: UINT) -> Option<bool> {
+    let mut item = get_menu_item_struct();
+    item.fMask = MIIM_STATE | MIIM_DATA;
+    if GetMenuItemInfoW(hmenu, pos, TRUE, &mut item) == 0 || item.dwItemData != CHECK_ITEM {
+        return None;
+    }
+    let checked = item.fState & MFS_CHECKED == 0;
+    item.fMask = MIIM_STATE;
+    item.fState = if checked {
+        item.fState | MFS_CHECKED
+    } else {
+        item.fState & !MFS_CHECKED
+    };
+    SetMenuItemInfoW(hmenu, pos, TRUE, &item);
+    Some(checked)
+}
+
+// A MENUITEMINFOW for a plain or check item, pointing into `text`.
+fn entry_item_info(text: &mut Vec<u16>, id: u32, checked: Option<bool>) -> MENUITEMINFOW {
+    let mut item = get_menu_item_struct();
+    item.fMask = MIIM_FTYPE | MIIM_STRING | MIIM_ID | MIIM_STATE;
+    item.fType = MFT_STRING;
+    item.wID = id;
+    item.dwTypeData = text.as_mut_ptr();
+    item.cch = (text.len() - 1) as u32;
+    if let Some(checked) = checked {
+        item.fMask |= MIIM_DATA;
+        item.dwItemData = CHECK_ITEM;
+        item.fState = if checked { MFS_CHECKED } else { MFS_UNCHECKED };
+    }
+    item
+}
+
+// Replaces the items of a dynamic submenu with what `populate` adds.
+unsafe fn populate_menu(hmenu: HMENU, populate: &Populate, ids: Arc<AtomicU32>) {
+    let mut builder = MenuBuilder::new(ids);
+    populate(&mut builder);
+    while GetMenuItemCount(hmenu) > 0 {
+        DeleteMenu(hmenu, 0, MF_BYPOSITION);
+    }
+    for (pos, entry) in builder.items.iter().enumerate() {
+        // Kept alive until the item is inserted.
+        let mut text;
+        let item = match entry {
+            BuilderItem::Entry { id, label, checked } => {
+                text = to_wstring(&win32_label(label));
+                entry_item_info(&mut text, *id, *checked)
+            }
+            BuilderItem::Separator => {
+                let mut item = get_menu_item_struct();
+                item.fMask = MIIM_FTYPE;
+                item.fType = MFT_SEPARATOR;
+                item
+            }
+        };
+        if InsertMenuItemW(hmenu, pos as UINT, TRUE, &item as *const MENUITEMINFOW) == 0 {
+            let e = get_win_os_error("Error inserting dynamic menu item");
+            warn!("{}", ErrorChain(&e));
+        }
+    }
+}
+
+// Submenus are known by their handle, the top level menu by 0, same as in
+// WM_MENUCOMMAND.
+fn lifecycle_menu_id(stash: &WindowsLoopData, hmenu: HMENU) -> u64 {
+    if hmenu == stash.info.hmenu {
+        0
+    } else {
+        hmenu as u64
+    }
 }
 
 unsafe extern "system" fn window_proc(
@@ -71,6 +194,43 @@ unsafe extern "system" fn window_proc(
     w_param: WPARAM,
     l_param: LPARAM,
 ) -> LRESULT {
+    if msg == WM_INITMENUPOPUP {
+        WININFO_STASH.with(|stash| {
+            let stash = stash.borrow();
+            let stash = stash.as_ref();
+            if let Some(stash) = stash {
+                let dynamic = stash
+                    .dynamic
+                    .lock()
+                    .unwrap()
+                    .get(&(w_param as u64))
+                    .cloned();
+                if let Some((populate, ids)) = dynamic {
+                    populate_menu(w_param as HMENU, &populate, ids);
+                }
+                let menu_id = lifecycle_menu_id(stash, w_param as HMENU);
+                stash
+                    .tx
+                    .send(Event::Lifecycle(MenuLifecycleEvent::MenuOpened(menu_id)))
+                    .ok();
+            }
+        });
+    }
+
+    if msg == WM_UNINITMENUPOPUP {
+        WININFO_STASH.with(|stash| {
+            let stash = stash.borrow();
+            let stash = stash.as_ref();
+            if let Some(stash) = stash {
+                let menu_id = lifecycle_menu_id(stash, w_param as HMENU);
+                stash
+                    .tx
+                    .send(Event::Lifecycle(MenuLifecycleEvent::MenuClosed(menu_id)))
+                    .ok();
+            }
+        });
+    }
+
     if msg == WM_MENUCOMMAND {
         WININFO_STASH.with(|stash| {
             let stash = stash.borrow();
@@ -83,12 +243,15 @@ unsafe extern "system" fn window_proc(
                 };
                 let item_id = GetMenuItemID(l_param as HMENU, w_param as i32) as i32;
                 if item_id != -1 {
+                    let checked = toggle_check_item(l_param as HMENU, w_param as UINT);
                     stash
                         .tx
-                        .send(SystrayEvent {
+                        .send(Event::Menu(SystrayEvent {
                             menu_id: hmenu as u64,
                             item_id: item_id as u32,
-                        })
+                            checked,
+                            timestamp: Instant::now(),
+                        }))
                         .ok();
                 }
             }
@@ -117,17 +280,48 @@ unsafe extern "system" fn window_proc(
             }
         });
     }
+    if msg == WM_USER + 1 {
+        let closed = |reason| NotificationEvent::Closed { id: 0, reason };
+        let events = match l_param as UINT {
+            NIN_BALLOONUSERCLICK => vec![
+                NotificationEvent::ActionInvoked {
+                    id: 0,
+                    action: "default".to_string(),
+                },
+                closed(CloseReason::Dismissed),
+            ],
+            // Sent both when the balloon times out and when the user closes
+            // it, so the reason is unknown.
+            NIN_BALLOONTIMEOUT => vec![closed(CloseReason::Undefined)],
+            NIN_BALLOONHIDE => vec![closed(CloseReason::Closed)],
+            _ => vec![],
+        };
+        WININFO_STASH.with(|stash| {
+            let stash = stash.borrow();
+            let stash = stash.as_ref();
+            if let Some(stash) = stash {
+                let current = stash.balloon.load(Ordering::SeqCst);
+                for mut event in events {
+                    match event {
+                        NotificationEvent::ActionInvoked { ref mut id, .. }
+                        | NotificationEvent::Closed { ref mut id, .. } => *id = current,
+                    }
+                    stash.tx.send(Event::Notification(event)).ok();
+                }
+            }
+        });
+    }
     if msg == WM_DESTROY {
         PostQuitMessage(0);
     }
     DefWindowProcW(h_wnd, msg, w_param, l_param)
 }
 
-fn get_nid_struct(hwnd: HWND) -> NOTIFYICONDATAW {
+fn get_nid_struct(hwnd: HWND, uid: UINT) -> NOTIFYICONDATAW {
     NOTIFYICONDATAW {
         cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as DWORD,
         hWnd: hwnd,
-        uID: 0x1 as UINT,
+        uID: uid,
         uFlags: 0 as UINT,
         uCallbackMessage: 0 as UINT,
         hIcon: 0 as HICON,
@@ -165,8 +359,10 @@ fn get_menu_item_struct() -> MENUITEMINFOW {
     }
 }
 
-unsafe fn init_window() -> Result<WindowInfo, SystrayError> {
-    let class_name = to_wstring("my_window");
+unsafe fn init_window(uid: UINT) -> Result<WindowInfo, SystrayError> {
+    // Named after the crate and its version, so that it clashes neither with
+    // classes of the application nor with another copy of this crate.
+    let class_name = to_wstring(concat!("systray-rs-", env!("CARGO_PKG_VERSION")));
     let hinstance: HINSTANCE = GetModuleHandleA(std::ptr::null_mut());
     let wnd = WNDCLASSW {
         style: 0,
@@ -180,7 +376,9 @@ unsafe fn init_window() -> Result<WindowInfo, SystrayError> {
         lpszMenuName: 0 as LPCWSTR,
         lpszClassName: class_name.as_ptr(),
     };
-    if RegisterClassW(&wnd) == 0 {
+    // The class is shared by the windows of all icons, only the first one
+    // registers it.
+    if RegisterClassW(&wnd) == 0 && GetLastError() != ERROR_CLASS_ALREADY_EXISTS {
         return Err(get_win_os_error("Error creating window class"));
     }
     let hwnd = CreateWindowExW(
@@ -200,10 +398,13 @@ unsafe fn init_window() -> Result<WindowInfo, SystrayError> {
     if hwnd.is_null() {
         return Err(get_win_os_error("Error creating window"));
     }
-    let mut nid = get_nid_struct(hwnd);
-    nid.uID = 0x1;
-    nid.uFlags = NIF_MESSAGE;
+    // Added hidden, so that the blank icon doesn't show before the
+    // Application is set up.
+    let mut nid = get_nid_struct(hwnd, uid);
+    nid.uFlags = NIF_MESSAGE | NIF_STATE;
     nid.uCallbackMessage = WM_USER + 1;
+    nid.dwState = NIS_HIDDEN;
+    nid.dwStateMask = NIS_HIDDEN;
     if Shell_NotifyIconW(NIM_ADD, &mut nid as *mut NOTIFYICONDATAW) == 0 {
         return Err(get_win_os_error("Error adding menu icon"));
     }
@@ -224,12 +425,13 @@ unsafe fn init_window() -> Result<WindowInfo, SystrayError> {
 
     Ok(WindowInfo {
         hwnd,
+        uid,
         hmenu,
         hinstance,
     })
 }
 
-unsafe fn run_loop() {
+unsafe fn run_loop() -> Result<(), SystrayError> {
     debug!("Running windows loop");
     // Run message loop
     let mut msg = MSG {
@@ -241,7 +443,9 @@ unsafe fn run_loop() {
         pt: POINT { x: 0, y: 0 },
     };
     loop {
-        GetMessageW(&mut msg, 0 as HWND, 0, 0);
+        if GetMessageW(&mut msg, 0 as HWND, 0, 0) == -1 {
+            return Err(get_win_os_error("Error getting message"));
+        }
         if msg.message == WM_QUIT {
             break;
         }
@@ -249,19 +453,65 @@ unsafe fn run_loop() {
         DispatchMessageW(&msg);
     }
     debug!("Leaving windows run loop");
+    Ok(())
+}
+
+// Tells the Application when the loop thread ends, even by panicking. The
+// Application keeps a sender of its own for Wakers, so it can't rely on the
+// channel closing.
+struct QuitOnExit(Sender<Event>);
+
+impl Drop for QuitOnExit {
+    fn drop(&mut self) {
+        self.0.send(Event::Quit).ok();
+    }
+}
+
+// Turns what the loop thread left behind, including a panic, into its
+// result.
+fn join_loop(handle: thread::JoinHandle<Result<(), SystrayError>>) -> Result<(), SystrayError> {
+    match handle.join() {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(SystrayError::ThreadPanicked(message))
+        }
+    }
 }
 
 pub struct Window {
     info: WindowInfo,
-    windows_loop: Option<thread::JoinHandle<()>>,
+    // Taken by `quit`.
+    windows_loop: Option<thread::JoinHandle<Result<(), SystrayError>>>,
+    balloon: Arc<AtomicU32>,
+    dynamic: DynamicMenus,
+    status: Cell<Status>,
+    shown: Cell<bool>,
+    // The notification area has no attention icon of its own, we swap
+    // between the two handles ourselves. Null until set.
+    icon: Cell<HICON>,
+    attention_icon: Cell<HICON>,
 }
 
 impl Window {
-    pub fn new(event_tx: Sender<SystrayEvent>) -> Result<Window, SystrayError> {
+    pub(crate) fn new(
+        tray: TrayId,
+        _options: &TrayOptions,
+        event_tx: Sender<Event>,
+    ) -> Result<Window, SystrayError> {
         let (tx, rx) = channel();
+        let balloon = Arc::new(AtomicU32::new(0));
+        let loop_balloon = balloon.clone();
+        let dynamic = DynamicMenus::default();
+        let loop_dynamic = dynamic.clone();
         let windows_loop = thread::spawn(move || {
+            let _quit = QuitOnExit(event_tx.clone());
             unsafe {
-                let i = init_window();
+                let i = init_window(tray.0);
                 let k;
                 match i {
                     Ok(j) => {
@@ -271,56 +521,90 @@ impl Window {
                     Err(e) => {
                         // If creation didn't work, return out of the thread.
                         tx.send(Err(e)).ok();
-                        return;
+                        return Ok(());
                     }
                 };
                 WININFO_STASH.with(|stash| {
                     let data = WindowsLoopData {
                         info: k,
                         tx: event_tx,
+                        balloon: loop_balloon,
+                        dynamic: loop_dynamic,
                     };
                     (*stash.borrow_mut()) = Some(data);
                 });
-                run_loop();
+                run_loop()
             }
         });
-        let info = match rx.recv().unwrap() {
-            Ok(i) => i,
-            Err(e) => {
+        let info = match rx.recv() {
+            Ok(Ok(i)) => i,
+            Ok(Err(e)) => {
                 return Err(e);
             }
+            // The thread died before it could tell.
+            Err(_) => {
+                join_loop(windows_loop)?;
+                return Err(SystrayError::ThreadGone);
+            }
         };
         let w = Window {
             info,
             windows_loop: Some(windows_loop),
+            balloon,
+            dynamic,
+            status: Cell::new(Status::Active),
+            shown: Cell::new(false),
+            icon: Cell::new(std::ptr::null_mut()),
+            attention_icon: Cell::new(std::ptr::null_mut()),
         };
         Ok(w)
     }
 
-    pub fn quit(&mut self) {
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            tooltips: true,
+            item_icons: true,
+            check_items: true,
+            menu_lifecycle_events: true,
+            status: true,
+            notifications: true,
+            resource_icons: true,
+            ..Default::default()
+        }
+    }
+
+    // Removes the icon and waits for the loop thread to end. Does nothing
+    // once that happened.
+    pub fn quit(&mut self) -> Result<(), SystrayError> {
+        let windows_loop = match self.windows_loop.take() {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let removed = self.remove_icon();
         unsafe {
             PostMessageW(self.info.hwnd, WM_DESTROY, 0 as WPARAM, 0 as LPARAM);
         }
-        if let Some(t) = self.windows_loop.take() {
-            t.join().ok();
-        }
+        let joined = join_loop(windows_loop);
+        removed.and(joined)
     }
 
-    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError> {
+    pub fn set_tooltip(&self, tooltip: &Tooltip) -> Result<TooltipFit, SystrayError> {
         // Add Tooltip
+        let tooltip = tooltip.to_plain_text();
         debug!("Setting tooltip to {}", tooltip);
-        let tt = to_wstring(tooltip);
-        let mut nid = get_nid_struct(self.info.hwnd);
-        for (i, c) in tt.iter().take(128).enumerate() {
-            nid.szTip[i] = *c;
-        }
+        let mut nid = get_nid_struct(self.info.hwnd, self.info.uid);
+        let fit = if copy_utf16(&mut nid.szTip, &tooltip) {
+            TooltipFit::Truncated
+        } else {
+            TooltipFit::Whole
+        };
         nid.uFlags = NIF_TIP;
         unsafe {
             if Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
                 return Err(get_win_os_error("Error setting tooltip"));
             }
         }
-        Ok(())
+        Ok(fit)
     }
 
     pub fn add_menu_entry(
@@ -328,16 +612,12 @@ impl Window {
         submenu: u64,
         menu_idx: u32,
         item_idx: u32,
-        item_name: &str,
+        label: &Label,
         icon: Option<Icon>,
+        checked: Option<bool>,
     ) -> Result<(), SystrayError> {
-        let mut st = to_wstring(item_name);
-        let mut item = get_menu_item_struct();
-        item.fMask = MIIM_FTYPE | MIIM_STRING | MIIM_ID | MIIM_STATE;
-        item.fType = MFT_STRING;
-        item.wID = menu_idx;
-        item.dwTypeData = st.as_mut_ptr();
-        item.cch = (item_name.len() * 2) as u32;
+        let mut st = to_wstring(&win32_label(label));
+        let mut item = entry_item_info(&mut st, menu_idx, checked);
         if let Some(icon) = icon {
             match icon {
                 Icon::File(icon_file) => {
@@ -387,10 +667,11 @@ impl Window {
         submenu: u64,
         menu_idx: u32,
         item_idx: u32,
-        item_name: &str,
+        label: &Label,
         icon: Option<Icon>,
     ) -> Result<u64, SystrayError> {
-        let mut st = to_wstring(item_name);
+        let item_name = win32_label(label);
+        let mut st = to_wstring(&item_name);
         let mut item = get_menu_item_struct();
         item.fMask = MIIM_FTYPE | MIIM_SUBMENU | MIIM_ID | MIIM_STATE | MIIM_STRING;
         item.fType = MFT_STRING;
@@ -423,6 +704,20 @@ impl Window {
         Ok(item.hSubMenu as u64)
     }
 
+    pub fn add_dynamic_submenu(
+        &self,
+        submenu: u64,
+        menu_idx: u32,
+        item_idx: u32,
+        label: &Label,
+        populate: Populate,
+        ids: Arc<AtomicU32>,
+    ) -> Result<u64, SystrayError> {
+        let hmenu = self.add_menu_group(submenu, menu_idx, item_idx, label, None)?;
+        self.dynamic.lock().unwrap().insert(hmenu, (populate, ids));
+        Ok(hmenu)
+    }
+
     pub fn add_menu_separator(
         &self,
         submenu: u64,
@@ -447,8 +742,23 @@ impl Window {
     }
 
     fn set_icon(&self, icon: HICON) -> Result<(), SystrayError> {
+        self.icon.set(icon);
+        self.show_icon()
+    }
+
+    // Shows the icon that goes with the current status.
+    fn show_icon(&self) -> Result<(), SystrayError> {
+        let attention = self.attention_icon.get();
+        let icon = if self.status.get() == Status::NeedsAttention && !attention.is_null() {
+            attention
+        } else {
+            self.icon.get()
+        };
+        if icon.is_null() {
+            return Ok(());
+        }
         unsafe {
-            let mut nid = get_nid_struct(self.info.hwnd);
+            let mut nid = get_nid_struct(self.info.hwnd, self.info.uid);
             nid.uFlags = NIF_ICON;
             nid.hIcon = icon;
             if Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
@@ -527,17 +837,38 @@ impl Window {
                 LR_LOADFROMFILE,
             ) as HICON;
             if hicon == std::ptr::null_mut() as HICON {
-                return Err(get_win_os_error(&format!(
-                    "Error loading icon from file {}",
-                    icon_file
-                )));
+                return Err(get_icon_load_error(icon_file));
             }
             hbitmap = self.icon_to_bitmap(hicon, ICON_SIZE)?;
         }
         Ok(hbitmap)
     }
 
-    pub fn set_icon_from_file(&self, icon_file: &str) -> Result<(), SystrayError> {
+    pub fn set_menu_item_tooltip(&self, _item: u32, _tooltip: &str) -> Result<(), SystrayError> {
+        Err(SystrayError::Unsupported(Capability::ItemTooltips))
+    }
+
+    // By id, which Windows looks up in the submenus as well.
+    pub fn set_menu_item_checked(&self, item: u32, checked: bool) -> Result<(), SystrayError> {
+        let mut info = get_menu_item_struct();
+        info.fMask = MIIM_STATE;
+        unsafe {
+            if GetMenuItemInfoW(self.info.hmenu, item, FALSE, &mut info) == 0 {
+                return Err(get_win_os_error("Error getting menu item"));
+            }
+            info.fState = if checked {
+                info.fState | MFS_CHECKED
+            } else {
+                info.fState & !MFS_CHECKED
+            };
+            if SetMenuItemInfoW(self.info.hmenu, item, FALSE, &info) == 0 {
+                return Err(get_win_os_error("Error checking menu item"));
+            }
+        }
+        Ok(())
+    }
+
+    fn load_icon_from_file(&self, icon_file: &str) -> Result<HICON, SystrayError> {
         let wstr_icon_file = to_wstring(&icon_file);
         let hicon;
         unsafe {
@@ -550,12 +881,50 @@ impl Window {
                 LR_LOADFROMFILE,
             ) as HICON;
             if hicon == std::ptr::null_mut() as HICON {
-                return Err(get_win_os_error("Error setting icon from file"));
+                return Err(get_icon_load_error(icon_file));
             }
         }
+        Ok(hicon)
+    }
+
+    pub fn set_icon_from_file(&self, icon_file: &str) -> Result<(), SystrayError> {
+        let hicon = self.load_icon_from_file(icon_file)?;
         self.set_icon(hicon)
     }
 
+    pub fn set_attention_icon_from_file(&self, icon_file: &str) -> Result<(), SystrayError> {
+        let hicon = self.load_icon_from_file(icon_file)?;
+        self.attention_icon.set(hicon);
+        self.show_icon()
+    }
+
+    pub fn set_status(&self, status: Status) -> Result<(), SystrayError> {
+        self.status.set(status);
+        self.apply_visibility()?;
+        self.show_icon()
+    }
+
+    pub fn show(&self) -> Result<(), SystrayError> {
+        self.shown.set(true);
+        self.apply_visibility()
+    }
+
+    // The icon is added hidden and stays so until shown. Passive icons are
+    // hidden too, the notification area has no place to move them to.
+    fn apply_visibility(&self) -> Result<(), SystrayError> {
+        let hidden = !self.shown.get() || self.status.get() == Status::Passive;
+        unsafe {
+            let mut nid = get_nid_struct(self.info.hwnd, self.info.uid);
+            nid.uFlags = NIF_STATE;
+            nid.dwStateMask = NIS_HIDDEN;
+            nid.dwState = if hidden { NIS_HIDDEN } else { 0 };
+            if Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) == 0 {
+                return Err(get_win_os_error("Error setting status"));
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_icon_from_buffer(
         &self,
         buffer: &[u8],
@@ -596,9 +965,65 @@ impl Window {
         }
     }
 
-    pub fn shutdown(&self) -> Result<(), SystrayError> {
+    // Shown as a balloon tip. Balloons have no buttons, so a click on the
+    // balloon is reported as the "default" action.
+    pub fn notify(&self, notification: &Notification) -> Result<u32, SystrayError> {
+        let mut nid = get_nid_struct(self.info.hwnd, self.info.uid);
+        nid.uFlags = NIF_INFO;
+        if copy_utf16(&mut nid.szInfoTitle, &notification.summary) {
+            warn!("Notification summary too long for a balloon, truncated");
+        }
+        if copy_utf16(&mut nid.szInfo, &notification.body) {
+            warn!("Notification body too long for a balloon, truncated");
+        }
+        nid.dwInfoFlags = match notification.urgency {
+            Urgency::Low => NIIF_NONE,
+            Urgency::Normal => NIIF_INFO,
+            Urgency::Critical => NIIF_ERROR,
+        };
+        if let Some(timeout) = notification.timeout {
+            unsafe {
+                *nid.u.uTimeout_mut() = timeout.as_millis() as UINT;
+            }
+        }
+        if let Some(ref icon_file) = notification.icon {
+            let hicon = unsafe {
+                LoadImageW(
+                    std::ptr::null_mut() as HINSTANCE,
+                    to_wstring(icon_file).as_ptr(),
+                    IMAGE_ICON,
+                    0,
+                    0,
+                    LR_LOADFROMFILE,
+                ) as HICON
+            };
+            if hicon == std::ptr::null_mut() as HICON {
+                return Err(unsafe { get_icon_load_error(icon_file) });
+            }
+            nid.hBalloonIcon = hicon;
+            nid.dwInfoFlags = NIIF_USER | NIIF_LARGE_ICON;
+        }
+        let id = self.balloon.fetch_add(1, Ordering::SeqCst) + 1;
+        unsafe {
+            // Read before DestroyIcon overwrites the last error.
+            let error = match Shell_NotifyIconW(NIM_MODIFY, &mut nid as *mut NOTIFYICONDATAW) {
+                0 => Some(get_win_os_error("Error showing notification")),
+                _ => None,
+            };
+            // The shell keeps its own copy of the balloon icon.
+            if nid.hBalloonIcon != std::ptr::null_mut() as HICON {
+                DestroyIcon(nid.hBalloonIcon);
+            }
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+        Ok(id)
+    }
+
+    fn remove_icon(&self) -> Result<(), SystrayError> {
         unsafe {
-            let mut nid = get_nid_struct(self.info.hwnd);
+            let mut nid = get_nid_struct(self.info.hwnd, self.info.uid);
             nid.uFlags = NIF_ICON;
             if Shell_NotifyIconW(NIM_DELETE, &mut nid as *mut NOTIFYICONDATAW) == 0 {
                 return Err(get_win_os_error("Error deleting icon from menu"));
@@ -610,6 +1035,6 @@ impl Window {
 
 impl Drop for Window {
     fn drop(&mut self) {
-        self.shutdown().ok();
+        self.quit().ok();
     }
 }
@@ -0,0 +1,452 @@
+// The `systray` command, a tray icon for shell scripts, see USAGE.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use systray::{
+    Action, Application, ErrorChain, MenuEntry, MenuEntryConfig, MenuEvent, MenuItemId,
+    SystrayError, Tooltip, TooltipFit, TrayConfig,
+};
+
+const USAGE: &str = "\
+Usage: systray [OPTIONS]
+
+Shows a tray icon and prints the command of each clicked menu item, or #ID
+for items without one, on a line of its own.
+
+Options:
+    --icon PATH       Icon file
+    --tooltip TEXT    Tooltip
+    --menu FILE       Icon, tooltip and menu in JSON, TOML or YAML, with the
+                      action of each item as its command
+    --id ID           Name of the tray, the same between runs
+    --title TITLE     Name of the tray shown to users
+    -h, --help        Show this help
+
+Commands read from stdin, one per line:
+    icon PATH                    Set the icon
+    tooltip TEXT                 Set the tooltip
+    add-item LABEL[|COMMAND]     Add an item at the end of the menu
+    add-check LABEL[|COMMAND]    Add an unchecked check item
+    add-separator                Add a separator
+    check ITEM                   Show the check mark of the items with this
+                                 command, or of the item #ID
+    uncheck ITEM                 Clear it
+    quit                         Remove the icon and exit, as does the end of
+                                 stdin
+";
+
+#[derive(Default)]
+struct Options {
+    icon: Option<String>,
+    tooltip: Option<String>,
+    menu: Option<String>,
+    id: Option<String>,
+    title: Option<String>,
+}
+
+struct State {
+    commands: HashMap<MenuItemId, String>,
+    // Lines from stdin, handled once the event loop is woken.
+    input: Receiver<String>,
+}
+
+type App = Application<State>;
+
+fn parse_args<I>(mut args: I) -> Result<Options, String>
+where
+    I: Iterator<Item = String>,
+{
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let slot = match arg.as_str() {
+            "--icon" => &mut options.icon,
+            "--tooltip" => &mut options.tooltip,
+            "--menu" => &mut options.menu,
+            "--id" => &mut options.id,
+            "--title" => &mut options.title,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("Unknown option {}", arg)),
+        };
+        *slot = Some(
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", arg))?,
+        );
+    }
+    Ok(options)
+}
+
+// Pairs the entries of the menu file with the menu made of them, which has
+// them in the same order.
+fn record_commands(
+    entries: &[MenuEntryConfig],
+    tree: &[MenuEntry],
+    commands: &mut HashMap<MenuItemId, String>,
+    submenus: &mut Vec<u64>,
+) {
+    for pair in entries.iter().zip(tree) {
+        match pair {
+            (
+                MenuEntryConfig::Item {
+                    action: Some(command),
+                    ..
+                },
+                MenuEntry::Item(item),
+            )
+            | (
+                MenuEntryConfig::Check {
+                    action: Some(command),
+                    ..
+                },
+                MenuEntry::Item(item),
+            )
+            | (
+                MenuEntryConfig::Radio {
+                    action: Some(command),
+                    ..
+                },
+                MenuEntry::Item(item),
+            ) => {
+                commands.insert(item.id, command.clone());
+            }
+            (MenuEntryConfig::Group { items, .. }, MenuEntry::Submenu(submenu)) => {
+                submenus.push(submenu.id);
+                record_commands(items, &submenu.entries, commands, submenus);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn print_click(app: &mut App, state: &mut State, event: &MenuEvent) {
+    let printed = match state.commands.get(&event.item) {
+        Some(command) => writeln!(io::stdout(), "{}", command),
+        None => writeln!(io::stdout(), "#{}", event.item),
+    };
+    // Nobody reads the clicks anymore.
+    if printed.is_err() {
+        app.quit().ok();
+    }
+}
+
+// The items named by `item`, `#` and an id or a command. Commands can be
+// numbers too, so ids need the `#`.
+fn find_items(state: &State, item: &str) -> Vec<MenuItemId> {
+    if let Some(id) = item.strip_prefix('#').and_then(|id| id.parse().ok()) {
+        return vec![id];
+    }
+    state
+        .commands
+        .iter()
+        .filter(|(_, command)| command.as_str() == item)
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+// A line read from stdin.
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Nothing,
+    Icon(&'a str),
+    Tooltip(&'a str),
+    AddItem {
+        label: &'a str,
+        command: &'a str,
+        check: bool,
+    },
+    AddSeparator,
+    Check {
+        item: &'a str,
+        checked: bool,
+    },
+    Quit,
+}
+
+fn parse_command(line: &str) -> Result<Command<'_>, String> {
+    let line = line.trim();
+    let (command, arg) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    Ok(match command {
+        "" => Command::Nothing,
+        "icon" => Command::Icon(arg),
+        "tooltip" => Command::Tooltip(arg),
+        "add-item" | "add-check" => {
+            let (label, item_command) = match arg.find('|') {
+                Some(i) => (arg[..i].trim(), arg[i + 1..].trim()),
+                None => (arg, ""),
+            };
+            Command::AddItem {
+                label,
+                command: item_command,
+                check: command == "add-check",
+            }
+        }
+        "add-separator" => Command::AddSeparator,
+        "check" | "uncheck" => Command::Check {
+            item: arg,
+            checked: command == "check",
+        },
+        "quit" => Command::Quit,
+        _ => return Err(format!("Unknown command {}", command)),
+    })
+}
+
+fn run_command(app: &mut App, state: &mut State, line: &str) -> Result<(), String> {
+    let result = match parse_command(line)? {
+        Command::Nothing => Ok(()),
+        Command::Icon(path) => app.set_icon_from_file(path),
+        Command::Tooltip(text) => app.set_tooltip(text).map(|fit| {
+            if fit == TooltipFit::Truncated {
+                eprintln!("systray: tooltip truncated");
+            }
+        }),
+        Command::AddItem {
+            label,
+            command,
+            check,
+        } => {
+            let item = if check {
+                app.add_check_item(0, label, false, |_, _, _| {})
+            } else {
+                app.add_menu_entry(0, label, None)
+            };
+            item.map(|item| {
+                if !command.is_empty() {
+                    state.commands.insert(item, command.to_owned());
+                }
+            })
+        }
+        Command::AddSeparator => app.add_menu_separator(0).map(|_| ()),
+        Command::Check { item, checked } => {
+            let items = find_items(state, item);
+            if items.is_empty() {
+                return Err(format!("No item {}", item));
+            }
+            items
+                .into_iter()
+                .try_for_each(|item| app.set_item_checked(item, checked))
+        }
+        Command::Quit => app.quit(),
+    };
+    result.map_err(|e| ErrorChain(&e).to_string())
+}
+
+fn run(options: Options) -> Result<(), SystrayError> {
+    let mut config = match options.menu {
+        Some(ref path) => TrayConfig::load(path)?,
+        None => TrayConfig::default(),
+    };
+    // The command line wins over the menu file.
+    let icon = options.icon.or_else(|| config.icon.take());
+    let tooltip = match options.tooltip {
+        Some(ref tooltip) => Some(Tooltip::from(tooltip.as_str())),
+        None => config.tooltip.take().map(|t| Tooltip::from(&t)),
+    };
+    let mut builder = Application::builder().quit_on_signals();
+    if let Some(ref id) = options.id {
+        builder = builder.id(id);
+    }
+    if let Some(ref title) = options.title {
+        builder = builder.title(title);
+    }
+    if let Some(ref icon) = icon {
+        builder = builder.icon(icon);
+    }
+    if let Some(tooltip) = tooltip {
+        builder = builder.tooltip(tooltip);
+    }
+    let (input_tx, input) = channel();
+    let mut app: App = builder
+        .menu(move |app| {
+            // Clicks are printed by the menu handlers, the actions only
+            // have to exist.
+            let mut actions: HashMap<&str, Action<State, ()>> = HashMap::new();
+            for name in config.action_names() {
+                actions.insert(name, Box::new(|_, _, _| {}));
+            }
+            app.apply_config(&config, actions)?;
+            let mut commands = HashMap::new();
+            let mut submenus = vec![0];
+            record_commands(&config.menu, &app.menu_tree(), &mut commands, &mut submenus);
+            for submenu in submenus {
+                app.set_menu_handler(submenu, print_click)?;
+            }
+            app.state_mut().commands = commands;
+            Ok(())
+        })
+        .build_with_state(State {
+            commands: HashMap::new(),
+            input,
+        })?;
+
+    let waker = app.waker();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if input_tx.send(line).is_err() || !waker.wake() {
+                return;
+            }
+        }
+        // The script is done with the icon.
+        if input_tx.send("quit".to_string()).is_ok() {
+            waker.wake();
+        }
+    });
+    app.on_wake(|app, state| {
+        while let Ok(line) = state.input.try_recv() {
+            if let Err(e) = run_command(app, state, &line) {
+                eprintln!("systray: {}", e);
+            }
+        }
+    });
+    app.wait_for_message();
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprint!("systray: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(options) {
+        eprintln!("systray: {}", ErrorChain(&e));
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parse_args_takes_values() {
+        let options = parse_args(args(&[
+            "--icon",
+            "a.png",
+            "--tooltip",
+            "Hi",
+            "--menu",
+            "m.yaml",
+            "--id",
+            "job",
+            "--title",
+            "Job",
+        ]))
+        .unwrap();
+        assert_eq!(options.icon.as_deref(), Some("a.png"));
+        assert_eq!(options.tooltip.as_deref(), Some("Hi"));
+        assert_eq!(options.menu.as_deref(), Some("m.yaml"));
+        assert_eq!(options.id.as_deref(), Some("job"));
+        assert_eq!(options.title.as_deref(), Some("Job"));
+
+        let options = parse_args(args(&[])).unwrap();
+        assert!(options.icon.is_none() && options.menu.is_none());
+    }
+
+    #[test]
+    fn parse_args_rejects_bad_options() {
+        assert_eq!(
+            parse_args(args(&["--icon"])).err().as_deref(),
+            Some("--icon needs a value")
+        );
+        assert_eq!(
+            parse_args(args(&["--colour", "red"])).err().as_deref(),
+            Some("Unknown option --colour")
+        );
+    }
+
+    #[test]
+    fn parse_command_splits_arguments() {
+        assert_eq!(parse_command("  "), Ok(Command::Nothing));
+        assert_eq!(
+            parse_command("icon  /tmp/a b.png "),
+            Ok(Command::Icon("/tmp/a b.png"))
+        );
+        assert_eq!(
+            parse_command("tooltip Build <b>3</b>"),
+            Ok(Command::Tooltip("Build <b>3</b>"))
+        );
+        assert_eq!(
+            parse_command("add-item _Open | open"),
+            Ok(Command::AddItem {
+                label: "_Open",
+                command: "open",
+                check: false
+            })
+        );
+        assert_eq!(
+            parse_command("add-check Notify"),
+            Ok(Command::AddItem {
+                label: "Notify",
+                command: "",
+                check: true
+            })
+        );
+        assert_eq!(parse_command("add-separator"), Ok(Command::AddSeparator));
+        assert_eq!(
+            parse_command("uncheck #4"),
+            Ok(Command::Check {
+                item: "#4",
+                checked: false
+            })
+        );
+        assert_eq!(parse_command("quit"), Ok(Command::Quit));
+        assert_eq!(
+            parse_command("remove 3"),
+            Err("Unknown command remove".to_string())
+        );
+    }
+
+    fn state(commands: &[(u32, &str)]) -> State {
+        let mut state = State {
+            commands: HashMap::new(),
+            input: channel().1,
+        };
+        for &(id, command) in commands {
+            state
+                .commands
+                .insert(id.to_string().parse().unwrap(), command.to_string());
+        }
+        state
+    }
+
+    fn ids(ids: &[u32]) -> Vec<MenuItemId> {
+        ids.iter()
+            .map(|id| id.to_string().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn find_items_by_id_or_command() {
+        let state = state(&[(1, "open"), (2, "3"), (5, "open")]);
+        let mut open = find_items(&state, "open");
+        open.sort();
+        assert_eq!(open, ids(&[1, 5]));
+        assert_eq!(find_items(&state, "#5"), ids(&[5]));
+        // Numbers are commands, ids take a `#`.
+        assert_eq!(find_items(&state, "3"), ids(&[2]));
+        assert_eq!(find_items(&state, "#3"), ids(&[3]));
+        assert!(find_items(&state, "close").is_empty());
+        assert!(find_items(&state, "#x").is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{Application, SystrayError, Tooltip, TooltipFit};
+
+/// What kind of application the tray icon belongs to. Panels use it to group
+/// and order their items.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Category {
+    /// The icon shows the status of the application, the usual case.
+    #[default]
+    ApplicationStatus,
+    /// Chat, mail and other communication with people.
+    Communications,
+    /// Services of the system, such as updates or backups.
+    SystemServices,
+    /// Hardware status, such as batteries or network devices.
+    Hardware,
+}
+
+// What the backends get to know about the tray before creating it.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) struct TrayOptions {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub category: Category,
+    pub icon_theme_path: Option<String>,
+    pub quit_on_signals: bool,
+}
+
+type MenuSetup<S, M> = Box<dyn FnOnce(&mut Application<S, M>) -> Result<(), SystrayError>>;
+
+/// Creates an `Application`, see `Application::builder`.
+///
+/// The icon, tooltip and menu given here are in place before the tray first
+/// shows up, so panels never see a blank icon.
+///
+/// The id, title, category and icon theme path are only used by AppIndicator
+/// on Linux, where they become the StatusNotifierItem properties of the same
+/// names. Panels use the id to tell applications apart and to remember their
+/// settings, so it should stay the same between runs. Without one, it is the
+/// name of the executable followed by the number of the tray in the process,
+/// like `myapp-1`, which then depends on the order the trays are created in.
+pub struct ApplicationBuilder<S = (), M = ()> {
+    options: TrayOptions,
+    icon: Option<String>,
+    tooltip: Option<Tooltip>,
+    menu: Option<MenuSetup<S, M>>,
+}
+
+impl<S, M> Default for ApplicationBuilder<S, M> {
+    fn default() -> Self {
+        ApplicationBuilder {
+            options: TrayOptions::default(),
+            icon: None,
+            tooltip: None,
+            menu: None,
+        }
+    }
+}
+
+impl<S, M> ApplicationBuilder<S, M> {
+    pub fn new() -> ApplicationBuilder<S, M> {
+        ApplicationBuilder::default()
+    }
+
+    /// A name for the tray that stays the same between runs. Each tray of a
+    /// process needs its own.
+    pub fn id(mut self, id: &str) -> Self {
+        self.options.id = Some(id.to_owned());
+        self
+    }
+
+    /// Human readable name of the tray, shown by panels that list their
+    /// items.
+    pub fn title(mut self, title: &str) -> Self {
+        self.options.title = Some(title.to_owned());
+        self
+    }
+
+    pub fn category(mut self, category: Category) -> Self {
+        self.options.category = category;
+        self
+    }
+
+    /// A directory with icons, searched for icons given by name.
+    pub fn icon_theme_path(mut self, path: &str) -> Self {
+        self.options.icon_theme_path = Some(path.to_owned());
+        self
+    }
+
+    /// The icon file, as for `Application::set_icon_from_file`.
+    pub fn icon(mut self, file: &str) -> Self {
+        self.icon = Some(file.to_owned());
+        self
+    }
+
+    /// The tooltip, as for `Application::set_tooltip`. Left out on backends
+    /// without tooltips.
+    pub fn tooltip<T>(mut self, tooltip: T) -> Self
+    where
+        T: Into<Tooltip>,
+    {
+        self.tooltip = Some(tooltip.into());
+        self
+    }
+
+    /// Fills the menu, called with the new Application before it is shown.
+    ///
+    /// ```no_run
+    /// let app = systray::Application::builder()
+    ///     .icon("resources/rust.png")
+    ///     .menu(|app| {
+    ///         app.add_menu_item(0, "Quit", None, |app, _| {
+    ///             app.quit().ok();
+    ///         })?;
+    ///         Ok(())
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn menu<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut Application<S, M>) -> Result<(), SystrayError> + 'static,
+    {
+        self.menu = Some(Box::new(f));
+        self
+    }
+
+    /// Quits the Application on SIGINT and SIGTERM, or on Ctrl-C and when the
+    /// console closes on Windows. Its quit hooks run and `wait_for_message`
+    /// returns, instead of the process ending with the icon still up.
+    ///
+    /// This replaces the handlers of the whole process while such trays are
+    /// up, so signals only stop it through them. The handlers from before
+    /// are back once the last of them quit, and a signal that finds none of
+    /// them left goes to those.
+    pub fn quit_on_signals(mut self) -> Self {
+        self.options.quit_on_signals = true;
+        self
+    }
+
+    /// Creates the tray icon, with `state` handed to every callback.
+    pub fn build_with_state(self, state: S) -> Result<Application<S, M>, SystrayError> {
+        let mut app = Application::create(&self.options, state)?;
+        if let Some(ref icon) = self.icon {
+            app.set_icon_from_file(icon)?;
+        }
+        if let Some(tooltip) = self.tooltip {
+            if app.capabilities().tooltips && app.set_tooltip(tooltip)? == TooltipFit::Truncated {
+                warn!("Tooltip too long for the backend, truncated");
+            }
+        }
+        if let Some(menu) = self.menu {
+            menu(&mut app)?;
+        }
+        app.show()?;
+        Ok(app)
+    }
+}
+
+impl ApplicationBuilder {
+    /// Creates the tray icon.
+    pub fn build(self) -> Result<Application, SystrayError> {
+        self.build_with_state(())
+    }
+}
@@ -0,0 +1,521 @@
+// Trays described in JSON, TOML or YAML files, so that their menus can be
+// changed without recompiling.
+use crate::{make_callback, Application, Callback, ErrorChain, MenuEvent, MenuItemId};
+use crate::{SystrayError, Tooltip, TooltipFit};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// A callback that items of a `TrayConfig` name in their `action`, see
+/// `Application::load_menu`.
+pub type Action<S, M> = Box<dyn FnMut(&mut Application<S, M>, &mut S, &MenuEvent)>;
+
+/// Icon, tooltip and menu of a tray, as stored in a file.
+///
+/// The entries of the menu are told apart by their `type`:
+///
+/// ```yaml
+/// icon: /usr/share/icons/hicolor/48x48/apps/backup.png
+/// tooltip: Backup
+/// menu:
+///   - { type: item, label: "_Run now", action: run }
+///   - type: group
+///     label: Mode
+///     items:
+///       - { type: radio, label: Full, group: mode, checked: true, action: mode }
+///       - { type: radio, label: Incremental, group: mode, action: mode }
+///   - { type: check, label: Notify when done, checked: true, action: notify }
+///   - type: separator
+///   - { type: item, label: "_Quit", action: quit }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrayConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<TooltipConfig>,
+    pub menu: Vec<MenuEntryConfig>,
+}
+
+/// The tooltip of a `TrayConfig`, either just a title or a title with a body.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TooltipConfig {
+    Title(String),
+    Full {
+        title: String,
+        #[serde(default)]
+        body: String,
+    },
+}
+
+/// An entry of the menu of a `TrayConfig`. Labels are parsed like those given
+/// to `Application::add_menu_item`.
+///
+/// Items without an action have no callback, their clicks only reach the
+/// handler set with `Application::set_menu_handler`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MenuEntryConfig {
+    Item {
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        action: Option<String>,
+    },
+    /// An item with a check mark, toggled on every click.
+    Check {
+        label: String,
+        #[serde(default)]
+        checked: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        action: Option<String>,
+    },
+    /// An item with a check mark that clears those of the other items with
+    /// the same `group` when clicked, wherever they are in the menu.
+    Radio {
+        label: String,
+        group: String,
+        #[serde(default)]
+        checked: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        action: Option<String>,
+    },
+    Separator,
+    /// A submenu.
+    Group {
+        label: String,
+        items: Vec<MenuEntryConfig>,
+    },
+}
+
+impl TrayConfig {
+    /// Reads a description in the format given by the extension of `path`,
+    /// one of `.json`, `.toml`, `.yaml` and `.yml`.
+    pub fn load<P>(path: P) -> Result<TrayConfig, SystrayError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| SystrayError::Os {
+            context: format!("Error reading {}", path.display()),
+            source: e,
+        })?;
+        let invalid = |source: Box<dyn Error + Send + Sync>| SystrayError::InvalidConfig {
+            path: path.display().to_string(),
+            source,
+        };
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|e| invalid(e.into())),
+            Some("toml") => toml::from_str(&text).map_err(|e| invalid(e.into())),
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&text).map_err(|e| invalid(e.into()))
+            }
+            _ => Err(invalid(
+                "Unknown format, expected .json, .toml, .yaml or .yml".into(),
+            )),
+        }
+    }
+
+    /// The actions named by the items of the menu, submenus included, in
+    /// menu order. Names shared by several items are repeated.
+    pub fn action_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        add_action_names(&self.menu, &mut names);
+        names
+    }
+}
+
+fn add_action_names<'a>(entries: &'a [MenuEntryConfig], names: &mut Vec<&'a str>) {
+    for entry in entries {
+        match entry {
+            MenuEntryConfig::Item {
+                action: Some(name), ..
+            }
+            | MenuEntryConfig::Check {
+                action: Some(name), ..
+            }
+            | MenuEntryConfig::Radio {
+                action: Some(name), ..
+            } => names.push(name),
+            MenuEntryConfig::Group { items, .. } => add_action_names(items, names),
+            _ => {}
+        }
+    }
+}
+
+impl<'a> From<&'a TooltipConfig> for Tooltip {
+    fn from(tooltip: &'a TooltipConfig) -> Self {
+        match tooltip {
+            TooltipConfig::Title(title) => Tooltip::new(title),
+            TooltipConfig::Full { title, body } => Tooltip::new(title).with_body(body),
+        }
+    }
+}
+
+// The items of each radio group, shared by their callbacks.
+type RadioGroups = HashMap<String, Rc<RefCell<Vec<MenuItemId>>>>;
+
+impl<S: 'static, M: 'static> Application<S, M> {
+    /// Sets up the tray as described in the file at `path`, see `TrayConfig`.
+    ///
+    /// The items call the entry of `actions` named by their `action`, several
+    /// items can share one. Names missing from `actions` fail with
+    /// `SystrayError::UnknownAction`, before anything is added.
+    ///
+    /// ```no_run
+    /// use std::collections::HashMap;
+    /// use systray::Action;
+    ///
+    /// let mut app = systray::Application::builder().build().unwrap();
+    /// let quit: Action<(), ()> = Box::new(|app, _, _| {
+    ///     app.quit().ok();
+    /// });
+    /// let mut actions = HashMap::new();
+    /// actions.insert("quit", quit);
+    /// app.load_menu("tray.yaml", actions).unwrap();
+    /// app.wait_for_message();
+    /// ```
+    pub fn load_menu<P>(
+        &mut self,
+        path: P,
+        actions: HashMap<&str, Action<S, M>>,
+    ) -> Result<(), SystrayError>
+    where
+        P: AsRef<Path>,
+    {
+        let config = TrayConfig::load(path)?;
+        self.apply_config(&config, actions)
+    }
+
+    /// Sets the icon and tooltip of `config` and adds its menu to the top
+    /// level menu, as `load_menu` does.
+    pub fn apply_config(
+        &mut self,
+        config: &TrayConfig,
+        actions: HashMap<&str, Action<S, M>>,
+    ) -> Result<(), SystrayError> {
+        // Checked up front, so that a missing one doesn't leave half a menu.
+        if let Some(name) = config
+            .action_names()
+            .into_iter()
+            .find(|name| !actions.contains_key(name))
+        {
+            return Err(SystrayError::UnknownAction(name.to_string()));
+        }
+        let actions: HashMap<&str, Callback<S, M>> = actions
+            .into_iter()
+            .map(|(name, f)| (name, make_callback(f)))
+            .collect();
+        if let Some(ref icon) = config.icon {
+            self.set_icon_from_file(icon)?;
+        }
+        if let Some(ref tooltip) = config.tooltip {
+            if self.capabilities().tooltips && self.set_tooltip(tooltip)? == TooltipFit::Truncated {
+                warn!("Tooltip too long for the backend, truncated");
+            }
+        }
+        self.add_config_entries(0, &config.menu, &actions, &mut RadioGroups::new())
+    }
+
+    fn add_config_entries(
+        &mut self,
+        submenu: u64,
+        entries: &[MenuEntryConfig],
+        actions: &HashMap<&str, Callback<S, M>>,
+        radios: &mut RadioGroups,
+    ) -> Result<(), SystrayError> {
+        for entry in entries {
+            match entry {
+                MenuEntryConfig::Item { label, action } => {
+                    let callback = find_action(actions, action)?;
+                    self.add_item(submenu, label, None, None, callback)?;
+                }
+                MenuEntryConfig::Check {
+                    label,
+                    checked,
+                    action,
+                } => {
+                    let callback = find_action(actions, action)?;
+                    self.add_item(submenu, label, None, Some(*checked), callback)?;
+                }
+                MenuEntryConfig::Radio {
+                    label,
+                    group,
+                    checked,
+                    action,
+                } => {
+                    let members = radios.entry(group.clone()).or_default().clone();
+                    let callback = radio_callback(members.clone(), find_action(actions, action)?);
+                    let item =
+                        self.add_item(submenu, label, None, Some(*checked), Some(callback))?;
+                    members.borrow_mut().push(item);
+                }
+                MenuEntryConfig::Separator => {
+                    self.add_menu_separator(submenu)?;
+                }
+                MenuEntryConfig::Group { label, items } => {
+                    let group = self.add_menu_group(submenu, label, None)?;
+                    self.add_config_entries(group, items, actions, radios)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn find_action<S, M>(
+    actions: &HashMap<&str, Callback<S, M>>,
+    name: &Option<String>,
+) -> Result<Option<Callback<S, M>>, SystrayError> {
+    match name {
+        Some(name) => match actions.get(name.as_str()) {
+            Some(f) => Ok(Some(f.clone())),
+            None => Err(SystrayError::UnknownAction(name.clone())),
+        },
+        None => Ok(None),
+    }
+}
+
+// The check mark of every item of a radio group once `clicked` was clicked.
+fn radio_checks(members: &[MenuItemId], clicked: MenuItemId) -> Vec<(MenuItemId, bool)> {
+    members
+        .iter()
+        .map(|&item| (item, item == clicked))
+        .collect()
+}
+
+// Checks the clicked item and clears the others of its group before running
+// the action. Clicking the checked item leaves it checked.
+fn radio_callback<S: 'static, M: 'static>(
+    members: Rc<RefCell<Vec<MenuItemId>>>,
+    action: Option<Callback<S, M>>,
+) -> Callback<S, M> {
+    make_callback(
+        move |app: &mut Application<S, M>, state: &mut S, event: &MenuEvent| {
+            for (item, checked) in radio_checks(&members.borrow(), event.item) {
+                if let Err(e) = app.set_item_checked(item, checked) {
+                    warn!("Can't update radio item: {}", ErrorChain(&e));
+                }
+            }
+            if let Some(ref f) = action {
+                let event = MenuEvent {
+                    checked: Some(true),
+                    ..event.clone()
+                };
+                (f.borrow_mut())(app, state, &event);
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const JSON: &str = r#"{
+        "icon": "backup.png",
+        "tooltip": { "title": "Backup", "body": "Last run <b>today</b>" },
+        "menu": [
+            { "type": "item", "label": "_Run now", "action": "run" },
+            { "type": "group", "label": "Mode", "items": [
+                { "type": "radio", "label": "Full", "group": "mode", "checked": true },
+                { "type": "radio", "label": "Incremental", "group": "mode" }
+            ] },
+            { "type": "check", "label": "Notify", "checked": true, "action": "notify" },
+            { "type": "separator" },
+            { "type": "item", "label": "_Quit" }
+        ]
+    }"#;
+
+    const TOML: &str = r#"
+        icon = "backup.png"
+
+        [tooltip]
+        title = "Backup"
+        body = "Last run <b>today</b>"
+
+        [[menu]]
+        type = "item"
+        label = "_Run now"
+        action = "run"
+
+        [[menu]]
+        type = "group"
+        label = "Mode"
+        items = [
+            { type = "radio", label = "Full", group = "mode", checked = true },
+            { type = "radio", label = "Incremental", group = "mode" },
+        ]
+
+        [[menu]]
+        type = "check"
+        label = "Notify"
+        checked = true
+        action = "notify"
+
+        [[menu]]
+        type = "separator"
+
+        [[menu]]
+        type = "item"
+        label = "_Quit"
+    "#;
+
+    const YAML: &str = "
+icon: backup.png
+tooltip:
+  title: Backup
+  body: Last run <b>today</b>
+menu:
+  - { type: item, label: _Run now, action: run }
+  - type: group
+    label: Mode
+    items:
+      - { type: radio, label: Full, group: mode, checked: true }
+      - { type: radio, label: Incremental, group: mode }
+  - { type: check, label: Notify, checked: true, action: notify }
+  - type: separator
+  - { type: item, label: _Quit }
+";
+
+    // Writes `text` to a file of its own and loads it.
+    fn load(name: &str, text: &str) -> Result<TrayConfig, SystrayError> {
+        let path = env::temp_dir().join(format!("systray-config-{}-{}", process::id(), name));
+        fs::write(&path, text).unwrap();
+        let config = TrayConfig::load(&path);
+        fs::remove_file(&path).ok();
+        config
+    }
+
+    #[test]
+    fn formats_describe_the_same_tray() {
+        let json = load("same.json", JSON).unwrap();
+        assert_eq!(load("same.toml", TOML).unwrap(), json);
+        assert_eq!(load("same.yaml", YAML).unwrap(), json);
+        assert_eq!(load("same.yml", YAML).unwrap(), json);
+
+        assert_eq!(json.icon.as_deref(), Some("backup.png"));
+        assert_eq!(
+            json.tooltip,
+            Some(TooltipConfig::Full {
+                title: "Backup".to_string(),
+                body: "Last run <b>today</b>".to_string(),
+            })
+        );
+        assert_eq!(json.menu.len(), 5);
+        assert_eq!(
+            json.menu[1],
+            MenuEntryConfig::Group {
+                label: "Mode".to_string(),
+                items: vec![
+                    MenuEntryConfig::Radio {
+                        label: "Full".to_string(),
+                        group: "mode".to_string(),
+                        checked: true,
+                        action: None,
+                    },
+                    MenuEntryConfig::Radio {
+                        label: "Incremental".to_string(),
+                        group: "mode".to_string(),
+                        checked: false,
+                        action: None,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn tooltip_can_be_a_title() {
+        let config = load("title.json", r#"{ "tooltip": "Backup" }"#).unwrap();
+        assert_eq!(
+            config.tooltip,
+            Some(TooltipConfig::Title("Backup".to_string()))
+        );
+        assert!(config.menu.is_empty());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for (name, text) in &[
+            ("unknown.json", r#"{ "icon": "a.png", "colour": "red" }"#),
+            ("unknown.toml", "icon = \"a.png\"\ncolour = \"red\"\n"),
+            ("unknown.yaml", "icon: a.png\ncolour: red\n"),
+        ] {
+            match load(name, text) {
+                Err(SystrayError::InvalidConfig { path, source }) => {
+                    assert!(path.ends_with(name));
+                    assert!(source.to_string().contains("colour"), "{}", source);
+                }
+                other => panic!("{} loaded: {:?}", name, other),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected() {
+        assert!(matches!(
+            load("tray.ini", "icon = a.png"),
+            Err(SystrayError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_actions_are_reported() {
+        let mut actions: HashMap<&str, Callback<(), ()>> = HashMap::new();
+        actions.insert(
+            "run",
+            make_callback(|_: &mut Application<(), ()>, _: &mut (), _: &MenuEvent| {}),
+        );
+
+        assert!(find_action(&actions, &None).unwrap().is_none());
+        assert!(find_action(&actions, &Some("run".to_string()))
+            .unwrap()
+            .is_some());
+        match find_action(&actions, &Some("quit".to_string())) {
+            Err(SystrayError::UnknownAction(name)) => assert_eq!(name, "quit"),
+            _ => panic!("Found an action named quit"),
+        }
+    }
+
+    #[test]
+    fn action_names_include_submenus() {
+        let mut config = load("names.json", JSON).unwrap();
+        assert_eq!(config.action_names(), ["run", "notify"]);
+        config.menu[1] = MenuEntryConfig::Group {
+            label: "More".to_string(),
+            items: vec![MenuEntryConfig::Item {
+                label: "Run".to_string(),
+                action: Some("run".to_string()),
+            }],
+        };
+        assert_eq!(config.action_names(), ["run", "run", "notify"]);
+    }
+
+    #[test]
+    fn radio_groups_check_only_the_clicked_item() {
+        let members = [MenuItemId(3), MenuItemId(4), MenuItemId(7)];
+        // The check marks as the callback leaves them after each click.
+        let mut checked: HashMap<MenuItemId, bool> = members
+            .iter()
+            .map(|&item| (item, item == members[0]))
+            .collect();
+        let mut click = |item| {
+            checked.extend(radio_checks(&members, item));
+            let mut shown: Vec<_> = checked.iter().filter(|(_, c)| **c).collect();
+            shown.sort();
+            shown.into_iter().map(|(item, _)| *item).collect::<Vec<_>>()
+        };
+        assert_eq!(click(MenuItemId(4)), [MenuItemId(4)]);
+        assert_eq!(click(MenuItemId(7)), [MenuItemId(7)]);
+        // Clicking the checked item again leaves it checked.
+        assert_eq!(click(MenuItemId(7)), [MenuItemId(7)]);
+    }
+}
@@ -0,0 +1,34 @@
+use crate::Markup;
+
+/// Label of a menu item, parsed from the string given to `add_menu_item`
+/// and friends.
+///
+/// The text before the first tab is markup with an optional mnemonic, see
+/// `Markup::parse_with_mnemonic`. The text after it is a hint for the
+/// keyboard shortcut of the item, such as `"Ctrl+Q"` in `"_Quit\tCtrl+Q"`,
+/// shown right aligned like in win32 menus. The tray only displays it, the
+/// application still has to handle the shortcut itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Label {
+    pub text: Markup,
+    pub accelerator: Option<String>,
+}
+
+impl Label {
+    pub fn parse(label: &str) -> Label {
+        let (text, accelerator) = match label.find('\t') {
+            Some(i) => (&label[..i], Some(label[i + 1..].trim())),
+            None => (label, None),
+        };
+        Label {
+            text: Markup::parse_with_mnemonic(text),
+            accelerator: accelerator.filter(|a| !a.is_empty()).map(str::to_owned),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Label {
+    fn from(label: &'a str) -> Self {
+        Label::parse(label)
+    }
+}
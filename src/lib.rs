@@ -7,90 +7,512 @@ extern crate log;
 extern crate glib;
 #[cfg(target_os = "linux")]
 extern crate gtk;
-#[cfg(target_os = "linux")]
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "linux", target_os = "windows"))]
 extern crate libc;
 #[cfg(target_os = "windows")]
 extern crate winapi;
 pub mod api;
+mod builder;
+#[cfg(feature = "serde")]
+mod config;
+mod label;
+mod markup;
+mod menu_builder;
+mod menu_info;
+mod notification;
+mod signals;
+#[cfg(all(target_os = "linux", feature = "testing"))]
+pub mod testing;
+mod tooltip;
 
+pub use builder::{ApplicationBuilder, Category};
+#[cfg(feature = "serde")]
+pub use config::{Action, MenuEntryConfig, TooltipConfig, TrayConfig};
+pub use label::Label;
+pub use markup::{Markup, Node};
+pub use menu_builder::MenuBuilder;
+pub use menu_info::{ItemInfo, ItemState, MenuEntry, SubmenuInfo};
+pub use notification::{CloseReason, Notification, NotificationEvent, Urgency};
+pub use tooltip::{Tooltip, TooltipFit};
+
+use builder::TrayOptions;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::sync::mpsc::{channel, Receiver};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Instant;
 
-#[derive(Clone, Debug)]
+/// Everything that can go wrong, with the error that caused it as `source()`
+/// where there is one.
+#[derive(Debug)]
 pub enum SystrayError {
-    OsError(String),
-    NotImplementedError,
-    UnknownError,
+    /// A call into the OS failed, `context` says which.
+    Os { context: String, source: io::Error },
+    /// No tray backend can be used, e.g. GTK failed to initialize or there is
+    /// neither a StatusNotifierHost nor an X display on Linux.
+    BackendUnavailable {
+        reason: String,
+        source: Option<Box<dyn Error + Send + Sync>>,
+    },
+    /// An icon file could not be loaded.
+    IconLoad { path: String, source: io::Error },
+    /// There is no submenu with this id.
+    InvalidMenuId(u64),
+    /// There is no menu item with this id, or it is of the wrong kind, such
+    /// as a plain item passed to `Application::set_item_checked`.
+    InvalidItemId(MenuItemId),
+    /// A tray description could not be read, see `TrayConfig::load`.
+    InvalidConfig {
+        path: String,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// A menu item names an action missing from those given to
+    /// `Application::load_menu`.
+    UnknownAction(String),
+    /// Talking to a D-Bus service failed, with the `dbus::Error` as source.
+    DBus(Box<dyn Error + Send + Sync>),
+    /// The thread running the backend is gone, usually because it panicked.
+    ThreadGone,
+    /// The thread running the backend panicked, with its panic message.
+    ThreadPanicked(String),
+    /// The backend in use lacks the feature, see `Application::capabilities`.
+    Unsupported(Capability),
 }
 
 pub struct SystrayEvent {
     menu_id: u64,
     item_id: u32,
+    checked: Option<bool>,
+    timestamp: Instant,
+}
+
+/// Identifies a menu item, as returned when adding it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MenuItemId(u32);
+
+// Shown and parsed as the plain number, for logs and text protocols.
+impl fmt::Display for MenuItemId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for MenuItemId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(MenuItemId)
+    }
+}
+
+/// Identifies a tray icon, see `Application::tray_id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TrayId(u32);
+
+// Every Application gets its own icon, numbered process wide.
+static NEXT_TRAY: AtomicU32 = AtomicU32::new(1);
+
+/// A click on a menu item, as passed to menu handlers and the callbacks of
+/// check items.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MenuEvent {
+    /// The tray icon whose menu was clicked.
+    pub tray: TrayId,
+    pub item: MenuItemId,
+    /// The submenu containing the item, 0 for the top level menu.
+    pub menu: u64,
+    /// The state of a check item after the click, `None` for other items.
+    pub checked: Option<bool>,
+    /// When the backend saw the click.
+    pub timestamp: Instant,
+}
+
+/// The menu or one of its submenus was shown or hidden, with the id of the
+/// submenu, 0 for the top level menu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuLifecycleEvent {
+    MenuOpened(u64),
+    MenuClosed(u64),
+}
+
+// Everything the backend threads report back to the Application.
+pub(crate) enum Event {
+    Menu(SystrayEvent),
+    Lifecycle(MenuLifecycleEvent),
+    Notification(NotificationEvent),
+    // The process was asked to stop, see `ApplicationBuilder::quit_on_signals`.
+    Quit,
+    // Sent by a `Waker`.
+    Wake,
+}
+
+/// Wakes the event loop of an Application from another thread, to run its
+/// `on_wake` callback there. See `Application::waker`.
+#[derive(Clone)]
+pub struct Waker(Sender<Event>);
+
+impl Waker {
+    /// Returns false once the Application is gone.
+    pub fn wake(&self) -> bool {
+        self.0.send(Event::Wake).is_ok()
+    }
 }
 
-impl std::error::Error for SystrayError {}
+impl SystrayError {
+    pub(crate) fn unavailable(reason: &str) -> SystrayError {
+        SystrayError::BackendUnavailable {
+            reason: reason.to_owned(),
+            source: None,
+        }
+    }
 
-impl std::fmt::Display for SystrayError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn dbus<E>(e: E) -> SystrayError
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        SystrayError::DBus(Box::new(e))
+    }
+}
+
+impl Error for SystrayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SystrayError::Os { source, .. } | SystrayError::IconLoad { source, .. } => Some(source),
+            SystrayError::BackendUnavailable { source, .. } => {
+                source.as_ref().map(|e| &**e as &(dyn Error + 'static))
+            }
+            SystrayError::DBus(e) | SystrayError::InvalidConfig { source: e, .. } => Some(&**e),
+            SystrayError::InvalidMenuId(_)
+            | SystrayError::InvalidItemId(_)
+            | SystrayError::UnknownAction(_)
+            | SystrayError::ThreadGone
+            | SystrayError::ThreadPanicked(_)
+            | SystrayError::Unsupported(_) => None,
+        }
+    }
+}
+
+// The causes are left to `source()`, see `ErrorChain` for the logs.
+impl fmt::Display for SystrayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            SystrayError::OsError(ref err_str) => write!(f, "OsError: {}", err_str),
-            SystrayError::NotImplementedError => write!(f, "Functionality is not implemented yet"),
-            SystrayError::UnknownError => write!(f, "Unknown error occurrred"),
+            SystrayError::Os { context, .. } => write!(f, "{}", context),
+            SystrayError::BackendUnavailable { reason, .. } => {
+                write!(f, "No tray backend available: {}", reason)
+            }
+            SystrayError::IconLoad { path, .. } => write!(f, "Error loading icon {}", path),
+            SystrayError::InvalidMenuId(id) => write!(f, "No submenu with id {}", id),
+            SystrayError::InvalidItemId(id) => write!(f, "No menu item with id {}", id),
+            SystrayError::InvalidConfig { path, .. } => {
+                write!(f, "Invalid tray description in {}", path)
+            }
+            SystrayError::UnknownAction(name) => write!(f, "No action named {:?}", name),
+            SystrayError::DBus(_) => write!(f, "D-Bus error"),
+            SystrayError::ThreadGone => write!(f, "The backend thread is gone"),
+            SystrayError::ThreadPanicked(message) => {
+                write!(f, "The backend thread panicked: {}", message)
+            }
+            SystrayError::Unsupported(capability) => {
+                write!(f, "The backend doesn't support {:?}", capability)
+            }
         }
     }
 }
 
-#[derive(Default)]
-pub struct MenuData {
+/// Shows an error followed by all its causes, separated by colons, for the
+/// places that can only log it. `SystrayError` leaves its causes to
+/// `source()`, so its `Display` alone doesn't show them.
+pub struct ErrorChain<'a>(pub &'a dyn Error);
+
+impl<'a> fmt::Display for ErrorChain<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(e) = source {
+            write!(f, ": {}", e)?;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
+/// How prominently the tray shows the icon, see `Application::set_status`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Status {
+    /// The icon is shown as usual.
+    #[default]
+    Active,
+    /// The icon is not important right now. Panels that support it move it
+    /// out of the way or hide it, the others hide it.
+    Passive,
+    /// The application wants the user's attention, the attention icon is
+    /// shown instead of the normal one.
+    NeedsAttention,
+}
+
+/// Features supported by the backend in use.
+///
+/// Backends differ a lot in what a tray icon can do, so check these flags
+/// before relying on a feature instead of waiting for a call to fail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// A tooltip is shown when hovering over the icon.
+    pub tooltips: bool,
+    /// Tooltips can contain markup such as bold text and line breaks.
+    pub markup_tooltips: bool,
+    /// Menu items can show an icon next to their label.
+    pub item_icons: bool,
+    /// Menu items can show a tooltip when hovered.
+    pub item_tooltips: bool,
+    /// Menu items can show a check mark.
+    pub check_items: bool,
+    /// `Status::Passive` and `Status::NeedsAttention` change how the icon is
+    /// shown.
+    pub status: bool,
+    /// Opening and closing the menu produces `MenuLifecycleEvent`s.
+    pub menu_lifecycle_events: bool,
+    /// A text label can be shown next to the icon.
+    pub labels: bool,
+    /// Clicking the icon itself produces an event instead of opening the menu.
+    pub activate_event: bool,
+    /// Scrolling over the icon produces an event.
+    pub scroll_event: bool,
+    /// Desktop notifications can be shown.
+    pub notifications: bool,
+    /// A second icon can be drawn on top of the main one.
+    pub overlay_icons: bool,
+    /// The icon can be animated.
+    pub animated: bool,
+    /// Icons can be loaded from the resources of the executable.
+    pub resource_icons: bool,
+}
+
+impl Capabilities {
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Tooltips => self.tooltips,
+            Capability::MarkupTooltips => self.markup_tooltips,
+            Capability::ItemIcons => self.item_icons,
+            Capability::ItemTooltips => self.item_tooltips,
+            Capability::CheckItems => self.check_items,
+            Capability::Status => self.status,
+            Capability::MenuLifecycleEvents => self.menu_lifecycle_events,
+            Capability::Labels => self.labels,
+            Capability::ActivateEvent => self.activate_event,
+            Capability::ScrollEvent => self.scroll_event,
+            Capability::Notifications => self.notifications,
+            Capability::OverlayIcons => self.overlay_icons,
+            Capability::Animated => self.animated,
+            Capability::ResourceIcons => self.resource_icons,
+        }
+    }
+}
+
+/// One of the features in `Capabilities`, named by
+/// `SystrayError::Unsupported`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Tooltips,
+    MarkupTooltips,
+    ItemIcons,
+    ItemTooltips,
+    CheckItems,
+    Status,
+    MenuLifecycleEvents,
+    Labels,
+    ActivateEvent,
+    ScrollEvent,
+    Notifications,
+    OverlayIcons,
+    Animated,
+    ResourceIcons,
+}
+
+pub struct MenuData<S = (), M = ()> {
     size: u32,
-    callbacks: HashMap<u32, Callback>,
+    label: Label,
+    dynamic: bool,
+    // Items, separators and submenus, in menu order.
+    children: Vec<MenuChild>,
+    callbacks: HashMap<u32, Callback<S, M>>,
+    handler: Option<Callback<S, M>>,
 }
 
-impl MenuData {
+impl<S, M> MenuData<S, M> {
     pub fn new() -> Self {
-        Default::default()
+        MenuData {
+            size: 0,
+            label: Label::default(),
+            dynamic: false,
+            children: Vec::new(),
+            callbacks: HashMap::new(),
+            handler: None,
+        }
+    }
+}
+
+impl<S, M> Default for MenuData<S, M> {
+    fn default() -> Self {
+        MenuData::new()
     }
 }
 
+#[derive(Clone, Copy)]
+enum MenuChild {
+    Item(MenuItemId),
+    Submenu(u64),
+}
+
+// What the Application remembers of an item, as the backends can't be asked.
+struct ItemRecord {
+    label: Label,
+    separator: bool,
+    state: ItemState,
+}
+
 pub enum Icon {
     File(String),
     Handle(api::api::IconHandle),
 }
 
-pub struct Application {
+/// A tray icon with its menu, carrying user state `S` that is passed to
+/// every callback.
+///
+/// Instead of callbacks, items can also carry a message of type `M`, which
+/// `next_message` returns when the item is clicked.
+///
+/// A process can have several Applications, each with its own icon, menu and
+/// events. Their event loops block, so run each one on its own thread.
+pub struct Application<S = (), M = ()> {
+    tray: TrayId,
     window: api::api::Window,
-    menu_idx: u32,
-    menu_data: HashMap<u64, MenuData>,
+    // Shared with the backend, which hands out ids to the items of dynamic
+    // submenus.
+    menu_idx: Arc<AtomicU32>,
+    menu_data: HashMap<u64, MenuData<S, M>>,
+    items: HashMap<MenuItemId, ItemRecord>,
     // Each platform-specific window module will set up its own thread for
     // dealing with the OS main loop. Use this channel for receiving events from
     // that thread.
-    rx: Receiver<SystrayEvent>,
+    rx: Receiver<Event>,
+    // Handed to Wakers.
+    event_tx: Sender<Event>,
+    notification_callback: Option<NotificationCallback<S, M>>,
+    lifecycle_callback: Option<LifecycleCallback<S, M>>,
+    wake_callback: Option<WakeCallback<S, M>>,
+    // Messages of the items added with `add_message_item`, by item id.
+    messages: HashMap<u32, M>,
+    quit_hooks: Vec<QuitHook<S>>,
+    // Lent to callbacks while they run, so they can get it next to the
+    // Application.
+    state: Option<S>,
+    // Set by `quit`, ends the event loops.
+    stopped: bool,
 }
 
-type Callback = Box<(dyn Fn(&mut Application) -> () + 'static)>;
+// Shared rather than boxed, so that a callback can be called while the
+// Application it lives in is borrowed mutably by the callback itself.
+type Callback<S, M> = Rc<RefCell<dyn FnMut(&mut Application<S, M>, &mut S, &MenuEvent)>>;
+type NotificationCallback<S, M> =
+    Rc<RefCell<dyn FnMut(&mut Application<S, M>, &mut S, &NotificationEvent)>>;
+type LifecycleCallback<S, M> =
+    Rc<RefCell<dyn FnMut(&mut Application<S, M>, &mut S, &MenuLifecycleEvent)>>;
+type WakeCallback<S, M> = Rc<RefCell<dyn FnMut(&mut Application<S, M>, &mut S)>>;
+type QuitHook<S> = Box<dyn FnOnce(&mut S)>;
 
-fn make_callback<F>(f: F) -> Callback
+fn make_callback<S, M, F>(f: F) -> Callback<S, M>
 where
-    F: std::ops::Fn(&mut Application) -> () + 'static,
+    F: FnMut(&mut Application<S, M>, &mut S, &MenuEvent) + 'static,
 {
-    Box::new(f) as Callback
+    Rc::new(RefCell::new(f))
 }
 
-impl Application {
-    pub fn new() -> Result<Application, SystrayError> {
+impl<S, M> Application<S, M> {
+    /// Starts building a tray icon:
+    ///
+    /// ```no_run
+    /// let app = systray::Application::builder()
+    ///     .id("build-monitor")
+    ///     .title("Build monitor")
+    ///     .icon("resources/rust.png")
+    ///     .tooltip("All builds passed")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder() -> ApplicationBuilder<S, M> {
+        ApplicationBuilder::new()
+    }
+
+    // Created hidden, the builder shows the tray once it is set up.
+    pub(crate) fn create(
+        options: &TrayOptions,
+        state: S,
+    ) -> Result<Application<S, M>, SystrayError> {
         let (event_tx, event_rx) = channel();
         let mut menu_data = HashMap::new();
         menu_data.insert(0, MenuData::new());
-        match api::api::Window::new(event_tx) {
-            Ok(w) => Ok(Application {
-                window: w,
-                menu_idx: 0,
-                menu_data,
-                rx: event_rx,
-            }),
-            Err(e) => Err(e),
+        let tray = TrayId(NEXT_TRAY.fetch_add(1, Ordering::SeqCst));
+        let window = api::api::Window::new(tray, options, event_tx.clone())?;
+        let app = Application {
+            tray,
+            window,
+            menu_idx: Arc::new(AtomicU32::new(0)),
+            menu_data,
+            items: HashMap::new(),
+            rx: event_rx,
+            event_tx: event_tx.clone(),
+            notification_callback: None,
+            lifecycle_callback: None,
+            wake_callback: None,
+            messages: HashMap::new(),
+            quit_hooks: Vec::new(),
+            state: Some(state),
+            stopped: false,
+        };
+        if options.quit_on_signals {
+            signals::register(tray, event_tx)?;
         }
+        Ok(app)
+    }
+
+    /// The user state.
+    ///
+    /// Panics when called from a callback, which gets the state as an
+    /// argument instead.
+    pub fn state(&self) -> &S {
+        self.state
+            .as_ref()
+            .expect("state is lent to a running callback")
+    }
+
+    /// The user state, mutably. Panics like `state`.
+    pub fn state_mut(&mut self) -> &mut S {
+        self.state
+            .as_mut()
+            .expect("state is lent to a running callback")
+    }
+
+    /// Quits and takes the Application apart, returning the user state.
+    pub fn into_state(mut self) -> S {
+        self.quit_logged();
+        self.state
+            .take()
+            .expect("state is lent to a running callback")
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.window.capabilities()
+    }
+
+    /// The tray icon of this Application, also found in its `MenuEvent`s.
+    pub fn tray_id(&self) -> TrayId {
+        self.tray
     }
 
     pub fn add_menu_group(
@@ -99,56 +521,317 @@ impl Application {
         item_name: &str,
         icon: Option<Icon>,
     ) -> Result<u64, SystrayError> {
-        if !self.menu_data.contains_key(&submenu) {
-            return Ok(0);
-        }
-        let idx = self.menu_data.get(&submenu).unwrap().size;
-        let subsubmenu =
-            self.window
-                .add_menu_group(submenu, self.menu_idx, idx, item_name, icon)?;
-        self.menu_data.insert(subsubmenu, MenuData::new());
-        self.menu_data.get_mut(&submenu).unwrap().size += 1;
-        self.menu_idx += 1;
+        let idx = self.next_position(submenu)?;
+        let label = Label::parse(item_name);
+        let subsubmenu = self
+            .window
+            .add_menu_group(submenu, self.next_id(), idx, &label, icon)?;
+        self.insert_submenu(submenu, subsubmenu, label, false);
         Ok(subsubmenu)
     }
 
+    /// Adds a submenu whose items are added by `populate` each time it is
+    /// opened, for lists that are expensive to compute or change often.
+    ///
+    /// `populate` runs on the thread of the backend, while the menu waits to
+    /// be shown, so it should be quick and can't touch the Application. The
+    /// items it adds have no callbacks, clicks on them reach the handler set
+    /// with `set_menu_handler` on the returned submenu.
+    pub fn add_dynamic_submenu<F>(
+        &mut self,
+        submenu: u64,
+        item_name: &str,
+        populate: F,
+    ) -> Result<u64, SystrayError>
+    where
+        F: Fn(&mut MenuBuilder) + Send + Sync + 'static,
+    {
+        let idx = self.next_position(submenu)?;
+        let label = Label::parse(item_name);
+        let dynamic = self.window.add_dynamic_submenu(
+            submenu,
+            self.next_id(),
+            idx,
+            &label,
+            Arc::new(populate),
+            self.menu_idx.clone(),
+        )?;
+        self.insert_submenu(submenu, dynamic, label, true);
+        Ok(dynamic)
+    }
+
+    fn next_id(&self) -> u32 {
+        self.menu_idx.fetch_add(1, Ordering::SeqCst)
+    }
+
+    // Records a submenu the backend added to `parent`.
+    fn insert_submenu(&mut self, parent: u64, submenu: u64, label: Label, dynamic: bool) {
+        let mut menu = MenuData::new();
+        menu.label = label;
+        menu.dynamic = dynamic;
+        self.menu_data.insert(submenu, menu);
+        let parent = self.menu_data.get_mut(&parent).unwrap();
+        parent.size += 1;
+        parent.children.push(MenuChild::Submenu(submenu));
+    }
+
+    // Records an item or separator the backend added to `submenu`.
+    fn insert_item(&mut self, submenu: u64, item: MenuItemId, record: ItemRecord) {
+        let menu = self.menu_data.get_mut(&submenu).unwrap();
+        menu.size += 1;
+        menu.children.push(MenuChild::Item(item));
+        self.items.insert(item, record);
+    }
+
+    // Where the next item of `submenu` goes, failing for unknown submenus.
+    fn next_position(&self, submenu: u64) -> Result<u32, SystrayError> {
+        self.menu_data
+            .get(&submenu)
+            .map(|menu| menu.size)
+            .ok_or(SystrayError::InvalidMenuId(submenu))
+    }
+
+    /// Whether `submenu` exists, 0 being the top level menu.
+    pub fn contains_menu(&self, submenu: u64) -> bool {
+        self.menu_data.contains_key(&submenu)
+    }
+
+    /// The items and separators added to `submenu`, in menu order. Submenus
+    /// are left out, as are the items of dynamic submenus, which only the
+    /// backend knows.
+    pub fn menu_items(&self, submenu: u64) -> Result<Vec<MenuItemId>, SystrayError> {
+        let menu = self.menu(submenu)?;
+        Ok(menu
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                MenuChild::Item(item) => Some(*item),
+                MenuChild::Submenu(_) => None,
+            })
+            .collect())
+    }
+
+    /// The whole menu, starting with the entries of the top level menu.
+    pub fn menu_tree(&self) -> Vec<MenuEntry> {
+        self.entries(&self.menu_data[&0])
+    }
+
+    /// The entries of `submenu`, each submenu among them with its own.
+    pub fn submenu_children(&self, submenu: u64) -> Result<Vec<MenuEntry>, SystrayError> {
+        Ok(self.entries(self.menu(submenu)?))
+    }
+
+    /// The label of `item` as it was added, empty for separators.
+    pub fn item_label(&self, item: MenuItemId) -> Result<Label, SystrayError> {
+        Ok(self.item(item)?.label.clone())
+    }
+
+    pub fn item_state(&self, item: MenuItemId) -> Result<ItemState, SystrayError> {
+        Ok(self.item(item)?.state.clone())
+    }
+
+    fn menu(&self, submenu: u64) -> Result<&MenuData<S, M>, SystrayError> {
+        self.menu_data
+            .get(&submenu)
+            .ok_or(SystrayError::InvalidMenuId(submenu))
+    }
+
+    fn item(&self, item: MenuItemId) -> Result<&ItemRecord, SystrayError> {
+        self.items
+            .get(&item)
+            .ok_or(SystrayError::InvalidItemId(item))
+    }
+
+    fn entries(&self, menu: &MenuData<S, M>) -> Vec<MenuEntry> {
+        menu.children
+            .iter()
+            .map(|child| match *child {
+                MenuChild::Item(id) => {
+                    let item = &self.items[&id];
+                    if item.separator {
+                        MenuEntry::Separator(id)
+                    } else {
+                        MenuEntry::Item(ItemInfo {
+                            id,
+                            label: item.label.clone(),
+                            state: item.state.clone(),
+                        })
+                    }
+                }
+                MenuChild::Submenu(id) => {
+                    let submenu = &self.menu_data[&id];
+                    MenuEntry::Submenu(SubmenuInfo {
+                        id,
+                        label: submenu.label.clone(),
+                        dynamic: submenu.dynamic,
+                        entries: self.entries(submenu),
+                    })
+                }
+            })
+            .collect()
+    }
+
+    fn add_item(
+        &mut self,
+        submenu: u64,
+        item_name: &str,
+        icon: Option<Icon>,
+        checked: Option<bool>,
+        callback: Option<Callback<S, M>>,
+    ) -> Result<MenuItemId, SystrayError> {
+        let idx = self.next_position(submenu)?;
+        let label = Label::parse(item_name);
+        let id = self.next_id();
+        self.window
+            .add_menu_entry(submenu, id, idx, &label, icon, checked)?;
+        if let Some(callback) = callback {
+            let menu = self.menu_data.get_mut(&submenu).unwrap();
+            menu.callbacks.insert(id, callback);
+        }
+        let record = ItemRecord {
+            label,
+            separator: false,
+            state: ItemState {
+                checked,
+                tooltip: None,
+            },
+        };
+        self.insert_item(submenu, MenuItemId(id), record);
+        Ok(MenuItemId(id))
+    }
+
+    /// Adds an item to `submenu`, calling `f` when it is clicked.
+    ///
+    /// `item_name` can contain a mnemonic, simple markup and an accelerator
+    /// hint after a tab, as in `"_Quit\tCtrl+Q"`. See `Label` for details.
+    ///
+    /// Like every method adding to a menu, this fails with
+    /// `SystrayError::InvalidMenuId` if `submenu` doesn't exist.
     pub fn add_menu_item<F>(
         &mut self,
         submenu: u64,
         item_name: &str,
         icon: Option<Icon>,
+        mut f: F,
+    ) -> Result<MenuItemId, SystrayError>
+    where
+        F: FnMut(&mut Application<S, M>, &mut S) + 'static,
+    {
+        let callback = make_callback(move |app, state, _: &MenuEvent| f(app, state));
+        self.add_item(submenu, item_name, icon, None, Some(callback))
+    }
+
+    /// Adds an item without a callback of its own. Its clicks only reach the
+    /// handler set with `set_menu_handler`.
+    pub fn add_menu_entry(
+        &mut self,
+        submenu: u64,
+        item_name: &str,
+        icon: Option<Icon>,
+    ) -> Result<MenuItemId, SystrayError> {
+        self.add_item(submenu, item_name, icon, None, None)
+    }
+
+    /// Adds an item with a check mark, toggled on every click. `f` gets the
+    /// new state in `MenuEvent::checked`.
+    pub fn add_check_item<F>(
+        &mut self,
+        submenu: u64,
+        item_name: &str,
+        checked: bool,
         f: F,
-    ) -> Result<u32, SystrayError>
+    ) -> Result<MenuItemId, SystrayError>
     where
-        F: std::ops::Fn(&mut Application) -> () + 'static,
+        F: FnMut(&mut Application<S, M>, &mut S, &MenuEvent) + 'static,
     {
-        if !self.menu_data.contains_key(&submenu) {
-            return Ok(0);
-        }
-        let idx = self.menu_data.get(&submenu).unwrap().size;
-        self.window
-            .add_menu_entry(submenu, self.menu_idx, idx, item_name, icon)?;
-        self.menu_data
+        self.add_item(
+            submenu,
+            item_name,
+            None,
+            Some(checked),
+            Some(make_callback(f)),
+        )
+    }
+
+    /// Sets a handler receiving every click in `submenu`, after the callback
+    /// of the item if it has one. Useful for generated lists of items, such
+    /// as recent files, which can then be added with `add_menu_entry`.
+    pub fn set_menu_handler<F>(&mut self, submenu: u64, f: F) -> Result<(), SystrayError>
+    where
+        F: FnMut(&mut Application<S, M>, &mut S, &MenuEvent) + 'static,
+    {
+        let menu = self
+            .menu_data
             .get_mut(&submenu)
-            .unwrap()
-            .callbacks
-            .insert(self.menu_idx, make_callback(f));
-        self.menu_data.get_mut(&submenu).unwrap().size += 1;
-        self.menu_idx += 1;
-        Ok(idx)
+            .ok_or(SystrayError::InvalidMenuId(submenu))?;
+        menu.handler = Some(make_callback(f));
+        Ok(())
     }
 
-    pub fn add_menu_separator(&mut self, submenu: u64) -> Result<u32, SystrayError> {
-        if !self.menu_data.contains_key(&submenu) {
-            return Ok(0);
-        }
-        let idx = self.menu_data.get(&submenu).unwrap().size;
-        if let Err(e) = self.window.add_menu_separator(submenu, self.menu_idx, idx) {
+    /// Adds an item that makes `next_message` return `message` when clicked,
+    /// for applications handling everything in one place instead of in
+    /// callbacks.
+    pub fn add_message_item(
+        &mut self,
+        submenu: u64,
+        item_name: &str,
+        message: M,
+    ) -> Result<MenuItemId, SystrayError> {
+        let item = self.add_item(submenu, item_name, None, None, None)?;
+        self.messages.insert(item.0, message);
+        Ok(item)
+    }
+
+    pub fn add_menu_separator(&mut self, submenu: u64) -> Result<MenuItemId, SystrayError> {
+        let idx = self.next_position(submenu)?;
+        let id = self.next_id();
+        if let Err(e) = self.window.add_menu_separator(submenu, id, idx) {
             return Err(e);
         }
-        self.menu_data.get_mut(&submenu).unwrap().size += 1;
-        self.menu_idx += 1;
-        Ok(idx)
+        let record = ItemRecord {
+            label: Label::default(),
+            separator: true,
+            state: ItemState::default(),
+        };
+        self.insert_item(submenu, MenuItemId(id), record);
+        Ok(MenuItemId(id))
+    }
+
+    /// Sets the text shown when hovering over `item`, to explain what it does
+    /// without a long label.
+    ///
+    /// Only shown where `Capabilities::item_tooltips` is set, which on Linux
+    /// is the XEmbed backend. StatusNotifierHosts draw the menu themselves,
+    /// with AppIndicator the text becomes the accessible name of the item,
+    /// exported as its dbusmenu `accessible-desc`, which screen readers
+    /// announce.
+    ///
+    /// Items of dynamic submenus can't get one, `item` has to be among the
+    /// `menu_items` of a menu.
+    pub fn set_menu_item_tooltip(
+        &mut self,
+        item: MenuItemId,
+        tooltip: &str,
+    ) -> Result<(), SystrayError> {
+        self.item(item)?;
+        self.window.set_menu_item_tooltip(item.0, tooltip)?;
+        self.items.get_mut(&item).unwrap().state.tooltip = Some(tooltip.to_owned());
+        Ok(())
+    }
+
+    /// Shows or clears the check mark of an item added with
+    /// `add_check_item`, without producing a click.
+    pub fn set_item_checked(
+        &mut self,
+        item: MenuItemId,
+        checked: bool,
+    ) -> Result<(), SystrayError> {
+        if self.item(item)?.state.checked.is_none() {
+            return Err(SystrayError::InvalidItemId(item));
+        }
+        self.window.set_menu_item_checked(item.0, checked)?;
+        self.items.get_mut(&item).unwrap().state.checked = Some(checked);
+        Ok(())
     }
 
     pub fn set_icon_from_file(&self, file: &str) -> Result<(), SystrayError> {
@@ -159,56 +842,247 @@ impl Application {
         self.window.set_icon_from_resource(resource)
     }
 
-    pub fn shutdown(&self) -> Result<(), SystrayError> {
-        self.window.shutdown()
+    /// Sets the icon shown instead of the normal one while the status is
+    /// `Status::NeedsAttention`. Without one, the normal icon stays.
+    pub fn set_attention_icon_from_file(&self, file: &str) -> Result<(), SystrayError> {
+        self.window.set_attention_icon_from_file(file)
+    }
+
+    /// Marks the icon active, passive or in need of attention. Only has an
+    /// effect where `Capabilities::status` is set.
+    pub fn set_status(&self, status: Status) -> Result<(), SystrayError> {
+        self.window.set_status(status)
+    }
+
+    #[deprecated(note = "use quit, which also stops the event loops")]
+    pub fn shutdown(&mut self) -> Result<(), SystrayError> {
+        self.quit()
+    }
+
+    /// Sets the tooltip of the icon, either from a plain string or a
+    /// `Tooltip` with title, body and icon.
+    ///
+    /// Backends that can only show text use `Tooltip::to_plain_text`. On
+    /// Windows, tooltips are limited to 127 UTF-16 code units and longer ones
+    /// are cut at a grapheme boundary, which the result tells.
+    pub fn set_tooltip<T>(&self, tooltip: T) -> Result<TooltipFit, SystrayError>
+    where
+        T: Into<Tooltip>,
+    {
+        self.window.set_tooltip(&tooltip.into())
+    }
+
+    /// Shows a desktop notification and returns its id.
+    pub fn notify(&self, notification: &Notification) -> Result<u32, SystrayError> {
+        self.window.notify(notification)
+    }
+
+    /// Sets the callback receiving action clicks and dismissals of the
+    /// notifications shown with `notify`.
+    pub fn on_notification<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut Application<S, M>, &mut S, &NotificationEvent) + 'static,
+    {
+        self.notification_callback = Some(Rc::new(RefCell::new(f)));
+    }
+
+    /// Adds a hook run when the Application quits, in the order they were
+    /// added, for saving state or stopping work that outlives the tray.
+    ///
+    /// Hooks run before the icon is removed. When `quit` is called from a
+    /// callback, which has the state, they run once that callback returns.
+    pub fn on_quit<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut S) + 'static,
+    {
+        self.quit_hooks.push(Box::new(f));
+    }
+
+    /// Sets the callback told when the menu or a submenu opens or closes,
+    /// for refreshing items lazily or pausing work while the menu is
+    /// visible. Only called where `Capabilities::menu_lifecycle_events` is
+    /// set.
+    pub fn on_menu_lifecycle<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut Application<S, M>, &mut S, &MenuLifecycleEvent) + 'static,
+    {
+        self.lifecycle_callback = Some(Rc::new(RefCell::new(f)));
+    }
+
+    /// Sets the callback run by the event loop whenever a `Waker` of this
+    /// Application is used.
+    pub fn on_wake<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut Application<S, M>, &mut S) + 'static,
+    {
+        self.wake_callback = Some(Rc::new(RefCell::new(f)));
     }
 
-    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), SystrayError> {
-        self.window.set_tooltip(tooltip)
+    /// Gives other threads a way to get work done on the thread of the event
+    /// loop, which owns the Application. They pass the work through a channel
+    /// of their own and wake the loop, whose `on_wake` callback then picks it
+    /// up.
+    pub fn waker(&self) -> Waker {
+        Waker(self.event_tx.clone())
     }
 
-    pub fn quit(&mut self) {
-        self.window.quit()
+    pub(crate) fn show(&self) -> Result<(), SystrayError> {
+        self.window.show()
+    }
+
+    /// Removes the icon and stops the backend, returning once its thread is
+    /// done with the icon. `wait_for_message` and `next_message` return after
+    /// the callback that called this.
+    ///
+    /// Errors of the backend thread, including panics, are reported here.
+    /// Calling it again, or dropping the Application afterwards, does
+    /// nothing.
+    pub fn quit(&mut self) -> Result<(), SystrayError> {
+        if self.stopped {
+            return Ok(());
+        }
+        self.stopped = true;
+        if self.state.is_some() {
+            self.run_quit_hooks();
+        }
+        let result = self.window.quit();
+        // Only now, as the console handler on Windows waits for this.
+        signals::unregister(self.tray);
+        result
+    }
+
+    fn run_quit_hooks(&mut self) {
+        let hooks = std::mem::take(&mut self.quit_hooks);
+        if let Some(state) = self.state.as_mut() {
+            for hook in hooks {
+                hook(state);
+            }
+        }
+    }
+
+    // For the event loops, which have nowhere to return the error to.
+    fn quit_logged(&mut self) {
+        if let Err(e) = self.quit() {
+            error!("Error stopping the tray: {}", ErrorChain(&e));
+        }
     }
 
     pub fn wait_for_message(&mut self) {
-        loop {
+        while !self.stopped {
             let msg;
             match self.rx.recv() {
-                Ok(m) => msg = m,
-                Err(_) => {
-                    self.quit();
+                Ok(Event::Menu(m)) => msg = m,
+                Ok(Event::Lifecycle(l)) => {
+                    self.dispatch_lifecycle_event(l);
+                    continue;
+                }
+                Ok(Event::Notification(n)) => {
+                    self.dispatch_notification_event(n);
+                    continue;
+                }
+                Ok(Event::Wake) => {
+                    self.dispatch_wake();
+                    continue;
+                }
+                Ok(Event::Quit) | Err(_) => {
+                    self.quit_logged();
                     break;
                 }
             }
-            if self.menu_data.contains_key(&msg.menu_id)
-                && self
-                    .menu_data
-                    .get(&msg.menu_id)
-                    .unwrap()
-                    .callbacks
-                    .contains_key(&msg.item_id)
-            {
-                let f = self
-                    .menu_data
-                    .get_mut(&msg.menu_id)
-                    .unwrap()
-                    .callbacks
-                    .remove(&msg.item_id)
-                    .unwrap();
-                f(self);
-                self.menu_data
-                    .get_mut(&msg.menu_id)
-                    .unwrap()
-                    .callbacks
-                    .insert(msg.item_id, f);
+            self.dispatch_menu_event(msg);
+        }
+    }
+
+    /// Waits for a click on an item added with `add_message_item` and returns
+    /// its message, running the callbacks of everything else that happens
+    /// meanwhile. Returns `None` once the tray is gone.
+    ///
+    /// Clicks on message items don't reach the menu handler. `wait_for_message`
+    /// ignores them altogether.
+    pub fn next_message(&mut self) -> Option<M>
+    where
+        M: Clone,
+    {
+        while !self.stopped {
+            match self.rx.recv() {
+                Ok(Event::Menu(m)) => {
+                    if let Some(message) = self.messages.get(&m.item_id) {
+                        return Some(message.clone());
+                    }
+                    self.dispatch_menu_event(m);
+                }
+                Ok(Event::Lifecycle(l)) => self.dispatch_lifecycle_event(l),
+                Ok(Event::Notification(n)) => self.dispatch_notification_event(n),
+                Ok(Event::Wake) => self.dispatch_wake(),
+                Ok(Event::Quit) | Err(_) => {
+                    self.quit_logged();
+                    return None;
+                }
             }
         }
+        None
+    }
+
+    fn dispatch_lifecycle_event(&mut self, event: MenuLifecycleEvent) {
+        if let Some(f) = self.lifecycle_callback.clone() {
+            self.lend_state(|app, state| (f.borrow_mut())(app, state, &event));
+        }
+    }
+
+    fn dispatch_notification_event(&mut self, event: NotificationEvent) {
+        if let Some(f) = self.notification_callback.clone() {
+            self.lend_state(|app, state| (f.borrow_mut())(app, state, &event));
+        }
+    }
+
+    fn dispatch_wake(&mut self) {
+        if let Some(f) = self.wake_callback.clone() {
+            self.lend_state(|app, state| (f.borrow_mut())(app, state));
+        }
+    }
+
+    // Runs `f` with the state taken out of the Application.
+    fn lend_state<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Application<S, M>, &mut S),
+    {
+        let mut state = self.state.take().expect("callbacks can't be nested");
+        f(self, &mut state);
+        self.state = Some(state);
+        // The callback quit, the hooks waited for the state.
+        if self.stopped {
+            self.run_quit_hooks();
+        }
+    }
+
+    fn dispatch_menu_event(&mut self, msg: SystrayEvent) {
+        if let Some(checked) = msg.checked {
+            if let Some(item) = self.items.get_mut(&MenuItemId(msg.item_id)) {
+                item.state.checked = Some(checked);
+            }
+        }
+        let event = MenuEvent {
+            tray: self.tray,
+            item: MenuItemId(msg.item_id),
+            menu: msg.menu_id,
+            checked: msg.checked,
+            timestamp: msg.timestamp,
+        };
+        let (callback, handler) = match self.menu_data.get(&msg.menu_id) {
+            Some(menu) => (
+                menu.callbacks.get(&msg.item_id).cloned(),
+                menu.handler.clone(),
+            ),
+            None => return,
+        };
+        for f in callback.iter().chain(handler.iter()) {
+            self.lend_state(|app, state| (f.borrow_mut())(app, state, &event));
+        }
     }
 }
 
-impl Drop for Application {
+impl<S, M> Drop for Application<S, M> {
     fn drop(&mut self) {
-        self.shutdown().ok();
+        self.quit_logged();
     }
 }
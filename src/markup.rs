@@ -0,0 +1,533 @@
+/// A piece of formatted text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Text(String),
+    Bold(Vec<Node>),
+    Italic(Vec<Node>),
+    Underline(Vec<Node>),
+    Link {
+        href: String,
+        children: Vec<Node>,
+    },
+    LineBreak,
+    /// Access key of a menu label, underlined when navigating the menu with
+    /// the keyboard. Only `parse_with_mnemonic` produces these.
+    Mnemonic(char),
+}
+
+/// Formatted text for tooltip bodies and menu labels.
+///
+/// `parse` understands the subset of HTML that StatusNotifierItem hosts
+/// render: `<b>`, `<i>`, `<u>`, `<a href="...">`, `<br>` and the entities
+/// `&amp;`, `&lt;`, `&gt;`, `&quot;`, `&apos;` and `&#...;`. Newlines are line
+/// breaks. Parsing is lenient: anything else, such as a lone `&` or `<`, an
+/// unknown tag or a stray closing tag, is kept as literal text, so plain
+/// strings come out unchanged.
+///
+/// The renderers escape all text, and links to anything but `http`, `https`
+/// and `mailto` URLs are dropped, keeping only their text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Markup {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tag {
+    Bold,
+    Italic,
+    Underline,
+    Link,
+    LineBreak,
+}
+
+// A parsed `<...>`, with the number of bytes it took.
+struct Element {
+    tag: Tag,
+    closing: bool,
+    href: Option<String>,
+    len: usize,
+}
+
+fn parse_element(s: &str) -> Option<Element> {
+    let end = s.find('>')?;
+    let inner = &s[1..end];
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (false, inner),
+    };
+    let inner = inner.strip_suffix('/').unwrap_or(inner).trim_end();
+    let name_len = inner
+        .find(|c: char| c.is_ascii_whitespace())
+        .unwrap_or(inner.len());
+    let (name, attributes) = inner.split_at(name_len);
+    let tag = match name.to_ascii_lowercase().as_str() {
+        "b" => Tag::Bold,
+        "i" => Tag::Italic,
+        "u" => Tag::Underline,
+        "a" => Tag::Link,
+        "br" => Tag::LineBreak,
+        _ => return None,
+    };
+    let attributes = attributes.trim();
+    let href = match tag {
+        Tag::Link if !closing => Some(parse_href(attributes)?),
+        _ if attributes.is_empty() => None,
+        _ => return None,
+    };
+    Some(Element {
+        tag,
+        closing,
+        href,
+        len: end + 1,
+    })
+}
+
+// Only `href="..."` (or single quoted) is accepted on links.
+fn parse_href(attributes: &str) -> Option<String> {
+    let value = attributes.strip_prefix("href")?.trim_start();
+    let value = value.strip_prefix('=')?.trim_start();
+    let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &value[1..];
+    let end = value.find(quote)?;
+    if !value[end + 1..].trim().is_empty() {
+        return None;
+    }
+    let mut href = String::new();
+    decode_entities(&value[..end], &mut href);
+    Some(href)
+}
+
+// Decodes the entity at the start of `s`, returning it with its length.
+fn parse_entity(s: &str) -> Option<(char, usize)> {
+    let end = s.find(';')?;
+    let name = &s[1..end];
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let code = match code.strip_prefix('x').or_else(|| code.strip_prefix('X')) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            std::char::from_u32(code).filter(|c| !c.is_control() || *c == '\n')?
+        }
+    };
+    Some((c, end + 1))
+}
+
+fn decode_entities(s: &str, out: &mut String) {
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match parse_entity(rest) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(Node::Text(ref mut last)) = nodes.last_mut() {
+        last.push_str(text);
+        return;
+    }
+    nodes.push(Node::Text(text.to_owned()));
+}
+
+fn close(stack: &mut Vec<(Tag, Option<String>, Vec<Node>)>) {
+    let (tag, href, children) = stack.pop().unwrap();
+    let node = match tag {
+        Tag::Bold => Node::Bold(children),
+        Tag::Italic => Node::Italic(children),
+        Tag::Underline => Node::Underline(children),
+        Tag::Link => Node::Link {
+            href: href.unwrap_or_default(),
+            children,
+        },
+        Tag::LineBreak => unreachable!(),
+    };
+    stack.last_mut().unwrap().2.push(node);
+}
+
+fn is_safe_href(href: &str) -> bool {
+    let href = href.trim_start().to_ascii_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| href.starts_with(scheme))
+}
+
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Sni,
+    Pango,
+    // Pango with `_` marking the mnemonic.
+    PangoMnemonic,
+    Plain,
+    // Plain text with `&` marking the mnemonic.
+    Win32,
+}
+
+fn render_text(text: &str, format: Format, out: &mut String) {
+    match format {
+        Format::Plain => out.push_str(text),
+        Format::Win32 => out.push_str(&text.replace('&', "&&")),
+        Format::PangoMnemonic => {
+            let mut escaped = String::new();
+            escape(text, &mut escaped);
+            out.push_str(&escaped.replace('_', "__"));
+        }
+        Format::Sni | Format::Pango => escape(text, out),
+    }
+}
+
+fn render(nodes: &[Node], format: Format, out: &mut String) {
+    for node in nodes {
+        let (open, close, children): (&str, &str, &[Node]) = match node {
+            Node::Text(text) => {
+                render_text(text, format, out);
+                continue;
+            }
+            Node::Mnemonic(c) => {
+                match format {
+                    Format::PangoMnemonic => out.push('_'),
+                    Format::Win32 => out.push('&'),
+                    _ => {}
+                }
+                render_text(c.encode_utf8(&mut [0; 4]), format, out);
+                continue;
+            }
+            Node::LineBreak => {
+                out.push_str(match format {
+                    Format::Sni => "<br/>",
+                    _ => "\n",
+                });
+                continue;
+            }
+            Node::Bold(children) => ("<b>", "</b>", children),
+            Node::Italic(children) => ("<i>", "</i>", children),
+            Node::Underline(children) => ("<u>", "</u>", children),
+            Node::Link { href, children } => match format {
+                Format::Sni if is_safe_href(href) => {
+                    out.push_str("<a href=\"");
+                    escape(href, out);
+                    out.push_str("\">");
+                    render(children, format, out);
+                    out.push_str("</a>");
+                    continue;
+                }
+                // Pango has no links, underline them like a browser would.
+                Format::Pango | Format::PangoMnemonic if is_safe_href(href) => {
+                    ("<u>", "</u>", children)
+                }
+                _ => ("", "", children),
+            },
+        };
+        if let Format::Plain | Format::Win32 = format {
+            render(children, format, out);
+            continue;
+        }
+        out.push_str(open);
+        render(children, format, out);
+        out.push_str(close);
+    }
+}
+
+impl Markup {
+    /// Parses `text` leniently, see the type documentation.
+    pub fn parse(text: &str) -> Markup {
+        Markup::parse_impl(text, false)
+    }
+
+    /// Parses a menu label, where the first `_` or `&` followed by a letter
+    /// or digit marks that character as the mnemonic, as in `"_Open"` or
+    /// `"&Open"`. A doubled `__` or `&&` is a literal `_` or `&`, and so is
+    /// a marker followed by anything else, as in `"Save & Quit"`. Entities
+    /// such as `&amp;` are not mnemonics.
+    pub fn parse_with_mnemonic(text: &str) -> Markup {
+        Markup::parse_impl(text, true)
+    }
+
+    fn parse_impl(text: &str, mnemonics: bool) -> Markup {
+        let markers: &[char] = if mnemonics {
+            &['<', '&', '\n', '\r', '_']
+        } else {
+            &['<', '&', '\n', '\r']
+        };
+        let mut found_mnemonic = false;
+        // Open elements with their children so far. The bottom entry holds
+        // the top level nodes, its tag is never looked at.
+        let mut stack: Vec<(Tag, Option<String>, Vec<Node>)> =
+            vec![(Tag::LineBreak, None, Vec::new())];
+        let mut rest = text;
+        while let Some(i) = rest.find(markers) {
+            if i > 0 {
+                push_text(&mut stack.last_mut().unwrap().2, &rest[..i]);
+                rest = &rest[i..];
+            }
+            let nodes = &mut stack.last_mut().unwrap().2;
+            if rest.starts_with("\r\n") || rest.starts_with('\n') || rest.starts_with('\r') {
+                nodes.push(Node::LineBreak);
+                rest = &rest[if rest.starts_with("\r\n") { 2 } else { 1 }..];
+            } else if rest.starts_with('_')
+                || (mnemonics && rest.starts_with('&') && parse_entity(rest).is_none())
+            {
+                // `_` only gets here when looking for mnemonics.
+                let marker = rest.chars().next().unwrap();
+                match rest[1..].chars().next() {
+                    Some(c) if c == marker => {
+                        push_text(nodes, &rest[..1]);
+                        rest = &rest[2..];
+                    }
+                    Some(c) if c.is_alphanumeric() && !found_mnemonic => {
+                        found_mnemonic = true;
+                        nodes.push(Node::Mnemonic(c));
+                        rest = &rest[1 + c.len_utf8()..];
+                    }
+                    _ => {
+                        push_text(nodes, &rest[..1]);
+                        rest = &rest[1..];
+                    }
+                }
+            } else if rest.starts_with('&') {
+                match parse_entity(rest) {
+                    Some(('\n', len)) => {
+                        nodes.push(Node::LineBreak);
+                        rest = &rest[len..];
+                    }
+                    Some((c, len)) => {
+                        push_text(nodes, c.encode_utf8(&mut [0; 4]));
+                        rest = &rest[len..];
+                    }
+                    None => {
+                        push_text(nodes, "&");
+                        rest = &rest[1..];
+                    }
+                }
+            } else {
+                let element = match parse_element(rest) {
+                    Some(e) => e,
+                    None => {
+                        push_text(nodes, "<");
+                        rest = &rest[1..];
+                        continue;
+                    }
+                };
+                match element.tag {
+                    Tag::LineBreak => nodes.push(Node::LineBreak),
+                    tag if !element.closing => stack.push((tag, element.href, Vec::new())),
+                    // Closing a tag also closes everything opened inside it.
+                    tag => match stack.iter().skip(1).rposition(|e| e.0 == tag) {
+                        Some(pos) => {
+                            while stack.len() > pos + 1 {
+                                close(&mut stack);
+                            }
+                        }
+                        None => push_text(&mut stack.last_mut().unwrap().2, &rest[..element.len]),
+                    },
+                }
+                rest = &rest[element.len..];
+            }
+        }
+        push_text(&mut stack.last_mut().unwrap().2, rest);
+        while stack.len() > 1 {
+            close(&mut stack);
+        }
+        Markup {
+            nodes: stack.pop().unwrap().2,
+        }
+    }
+
+    /// Markup holding `text` as is, without parsing it.
+    pub fn text(text: &str) -> Markup {
+        Markup {
+            nodes: vec![Node::Text(text.to_owned())],
+        }
+    }
+
+    /// Renders to the HTML subset understood by StatusNotifierItem hosts.
+    pub fn to_sni_markup(&self) -> String {
+        let mut out = String::new();
+        render(&self.nodes, Format::Sni, &mut out);
+        out
+    }
+
+    /// Renders to Pango markup, as used by GTK labels and tooltips.
+    pub fn to_pango_markup(&self) -> String {
+        let mut out = String::new();
+        render(&self.nodes, Format::Pango, &mut out);
+        out
+    }
+
+    /// Renders to Pango markup with the mnemonic marked by `_`, for
+    /// `gtk_label_set_markup_with_mnemonic`.
+    pub fn to_pango_mnemonic_markup(&self) -> String {
+        let mut out = String::new();
+        render(&self.nodes, Format::PangoMnemonic, &mut out);
+        out
+    }
+
+    /// Drops all formatting, keeping line breaks as newlines.
+    pub fn to_plain_text(&self) -> String {
+        let mut out = String::new();
+        render(&self.nodes, Format::Plain, &mut out);
+        out
+    }
+
+    /// Plain text with the mnemonic marked by `&`, as win32 menus take it.
+    pub fn to_win32_text(&self) -> String {
+        let mut out = String::new();
+        render(&self.nodes, Format::Win32, &mut out);
+        out
+    }
+}
+
+impl<'a> From<&'a str> for Markup {
+    fn from(text: &'a str) -> Self {
+        Markup::parse(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_owned())
+    }
+
+    #[test]
+    fn parse_nests_elements() {
+        let markup = Markup::parse("<b>bold <i>both</i></b> plain");
+        assert_eq!(
+            markup.nodes,
+            [
+                Node::Bold(vec![text("bold "), Node::Italic(vec![text("both")])]),
+                text(" plain"),
+            ]
+        );
+        assert_eq!(markup.to_sni_markup(), "<b>bold <i>both</i></b> plain");
+        assert_eq!(markup.to_plain_text(), "bold both plain");
+    }
+
+    #[test]
+    fn parse_closes_what_is_left_open() {
+        // Closing the outer tag closes the inner one, the end closes the rest.
+        let markup = Markup::parse("<b><i>a</b>b<u>c");
+        assert_eq!(
+            markup.nodes,
+            [
+                Node::Bold(vec![Node::Italic(vec![text("a")])]),
+                text("b"),
+                Node::Underline(vec![text("c")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_keeps_unknown_tags_as_text() {
+        let markup = Markup::parse("<span>x</span> </b> a < b");
+        assert_eq!(markup.nodes, [text("<span>x</span> </b> a < b")]);
+        assert_eq!(
+            markup.to_pango_markup(),
+            "&lt;span&gt;x&lt;/span&gt; &lt;/b&gt; a &lt; b"
+        );
+    }
+
+    #[test]
+    fn parse_line_breaks() {
+        let markup = Markup::parse("a<br>b<BR/>c\r\nd&#10;e");
+        assert_eq!(markup.to_sni_markup(), "a<br/>b<br/>c<br/>d<br/>e");
+        assert_eq!(markup.to_plain_text(), "a\nb\nc\nd\ne");
+    }
+
+    #[test]
+    fn entities_are_decoded_and_escaped_again() {
+        let markup = Markup::parse("&lt;a&gt; &amp; &quot;b&apos; &#x263A; & &bogus;");
+        assert_eq!(markup.nodes, [text("<a> & \"b' \u{263A} & &bogus;")]);
+        assert_eq!(
+            markup.to_sni_markup(),
+            "&lt;a&gt; &amp; &quot;b&#39; \u{263A} &amp; &amp;bogus;"
+        );
+        // Control characters other than newlines stay literal.
+        assert_eq!(Markup::parse("&#7;").nodes, [text("&#7;")]);
+    }
+
+    #[test]
+    fn text_is_not_parsed() {
+        assert_eq!(Markup::text("<b>").to_sni_markup(), "&lt;b&gt;");
+    }
+
+    #[test]
+    fn links_keep_safe_schemes_only() {
+        let markup = Markup::parse(
+            "<a href=\"https://example.com/?a=1&amp;b=2\">web</a> \
+             <a href='mailto:me@example.com'>mail</a> \
+             <a href=\" JavaScript:alert(1)\">evil</a> \
+             <a href=\"file:///etc/passwd\">file</a>",
+        );
+        assert_eq!(
+            markup.to_sni_markup(),
+            "<a href=\"https://example.com/?a=1&amp;b=2\">web</a> \
+             <a href=\"mailto:me@example.com\">mail</a> evil file"
+        );
+        assert_eq!(markup.to_pango_markup(), "<u>web</u> <u>mail</u> evil file");
+        assert_eq!(markup.to_plain_text(), "web mail evil file");
+    }
+
+    #[test]
+    fn links_need_a_quoted_href() {
+        let markup = Markup::parse("<a href=x>y</a> <a title=\"t\">z</a>");
+        assert_eq!(markup.nodes, [text("<a href=x>y</a> <a title=\"t\">z</a>")]);
+    }
+
+    #[test]
+    fn mnemonics() {
+        let markup = Markup::parse_with_mnemonic("_Open <b>__f</b>");
+        assert_eq!(
+            markup.nodes,
+            [
+                Node::Mnemonic('O'),
+                text("pen "),
+                Node::Bold(vec![text("_f")]),
+            ]
+        );
+        assert_eq!(markup.to_pango_mnemonic_markup(), "_Open <b>__f</b>");
+        assert_eq!(markup.to_win32_text(), "&Open _f");
+
+        // Only the first marker counts, a lone `&` is literal.
+        let markup = Markup::parse_with_mnemonic("Save & _Quit &Now &amp;");
+        assert_eq!(markup.to_win32_text(), "Save && &Quit &&Now &&");
+        assert_eq!(
+            markup.to_pango_mnemonic_markup(),
+            "Save &amp; _Quit &amp;Now &amp;"
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{Label, MenuItemId};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+// Only read by the backends that have dynamic submenus.
+#[cfg_attr(target_os = "macos", allow(dead_code))]
+pub(crate) enum BuilderItem {
+    Entry {
+        id: u32,
+        label: Label,
+        checked: Option<bool>,
+    },
+    Separator,
+}
+
+/// Collects the items of a dynamic submenu, see
+/// `Application::add_dynamic_submenu`.
+///
+/// Items have no callbacks of their own, their clicks reach the handler of
+/// the submenu. The ids returned here tell them apart, and are fresh every
+/// time the submenu is populated.
+pub struct MenuBuilder {
+    ids: Arc<AtomicU32>,
+    pub(crate) items: Vec<BuilderItem>,
+}
+
+// Runs on the backend thread whenever the submenu opens.
+pub(crate) type Populate = Arc<dyn Fn(&mut MenuBuilder) + Send + Sync>;
+
+impl MenuBuilder {
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    pub(crate) fn new(ids: Arc<AtomicU32>) -> MenuBuilder {
+        MenuBuilder {
+            ids,
+            items: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, item_name: &str, checked: Option<bool>) -> MenuItemId {
+        let id = self.ids.fetch_add(1, Ordering::SeqCst);
+        self.items.push(BuilderItem::Entry {
+            id,
+            label: Label::parse(item_name),
+            checked,
+        });
+        MenuItemId(id)
+    }
+
+    pub fn add_item(&mut self, item_name: &str) -> MenuItemId {
+        self.push(item_name, None)
+    }
+
+    pub fn add_check_item(&mut self, item_name: &str, checked: bool) -> MenuItemId {
+        self.push(item_name, Some(checked))
+    }
+
+    pub fn add_separator(&mut self) {
+        self.items.push(BuilderItem::Separator);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{Label, MenuItemId};
+
+/// An entry of the menu, as returned by `Application::menu_tree`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MenuEntry {
+    Item(ItemInfo),
+    Separator(MenuItemId),
+    Submenu(SubmenuInfo),
+}
+
+/// A menu item and what it currently shows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemInfo {
+    pub id: MenuItemId,
+    pub label: Label,
+    pub state: ItemState,
+}
+
+/// The parts of an item that change after it was added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ItemState {
+    /// Whether the check mark is shown, `None` for items without one. Follows
+    /// the clicks once the Application has seen their events.
+    pub checked: Option<bool>,
+    /// Set with `Application::set_menu_item_tooltip`.
+    pub tooltip: Option<String>,
+}
+
+/// A submenu with its entries.
+///
+/// Dynamic submenus are filled by the backend each time they open, so their
+/// entries are only those added through the Application.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubmenuInfo {
+    pub id: u64,
+    pub label: Label,
+    pub dynamic: bool,
+    pub entries: Vec<MenuEntry>,
+}
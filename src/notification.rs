@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+/// How important a notification is. Servers may style or sort
+/// notifications by urgency, and usually don't expire critical ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+/// A desktop notification, shown with `Application::notify`.
+#[derive(Clone, Debug, Default)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    /// Icon name from the icon theme, or path to an icon file.
+    pub icon: Option<String>,
+    pub urgency: Urgency,
+    /// How long the notification stays up, or `None` for the server default.
+    pub timeout: Option<Duration>,
+    /// Buttons as `(key, label)` pairs. The key comes back in
+    /// `NotificationEvent::ActionInvoked`. The `"default"` key is invoked
+    /// when the notification itself is clicked.
+    pub actions: Vec<(String, String)>,
+}
+
+impl Notification {
+    pub fn new(summary: &str, body: &str) -> Self {
+        Notification {
+            summary: summary.to_owned(),
+            body: body.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Why a notification went away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    Expired,
+    Dismissed,
+    Closed,
+    Undefined,
+}
+
+/// What happened to a notification. `id` is the value returned by
+/// `Application::notify`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotificationEvent {
+    ActionInvoked { id: u32, action: String },
+    Closed { id: u32, reason: CloseReason },
+}
@@ -0,0 +1,302 @@
+// Quitting on SIGINT and SIGTERM, or Ctrl-C and closing the console on
+// Windows, for the trays built with `ApplicationBuilder::quit_on_signals`.
+//
+// The handlers only wake a thread, which tells the event loops of those trays
+// to quit. Their hooks and the removal of the icons run there, not in the
+// handler.
+use crate::{Event, SystrayError, TrayId};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Condvar, Mutex};
+
+// The trays to tell, None while the handlers aren't installed.
+static TRAYS: Mutex<Option<HashMap<TrayId, Sender<Event>>>> = Mutex::new(None);
+// Notified whenever a tray is done quitting.
+static UNREGISTERED: Condvar = Condvar::new();
+
+pub(crate) fn register(tray: TrayId, event_tx: Sender<Event>) -> Result<(), SystrayError> {
+    let mut trays = TRAYS.lock().unwrap();
+    if trays.is_none() {
+        imp::install()?;
+    }
+    trays
+        .get_or_insert_with(HashMap::new)
+        .insert(tray, event_tx);
+    Ok(())
+}
+
+// Called once the tray ran its quit hooks. The handlers from before go back
+// in place with the last tray.
+pub(crate) fn unregister(tray: TrayId) {
+    let mut trays = TRAYS.lock().unwrap();
+    if let Some(map) = trays.as_mut() {
+        map.remove(&tray);
+        if map.is_empty() {
+            *trays = None;
+            imp::uninstall();
+        }
+    }
+    UNREGISTERED.notify_all();
+}
+
+// Tells every tray to quit, returning those that could be told.
+#[cfg_attr(not(any(target_os = "linux", target_os = "windows")), allow(dead_code))]
+fn quit_all() -> Vec<TrayId> {
+    match TRAYS.lock().unwrap().as_ref() {
+        Some(trays) => trays
+            .iter()
+            .filter(|(_, tx)| tx.send(Event::Quit).is_ok())
+            .map(|(tray, _)| *tray)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// Puts the handlers from before back in place, for a signal that arrived
+// with no tray left to tell.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn restore() {
+    let mut trays = TRAYS.lock().unwrap();
+    if trays.take().is_some() {
+        imp::uninstall();
+    }
+}
+
+// Waits until the trays in `told` quit.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn wait_until_unregistered(told: &[TrayId]) {
+    let mut trays = TRAYS.lock().unwrap();
+    while matches!(trays.as_ref(), Some(t) if told.iter().any(|tray| t.contains_key(tray))) {
+        trays = UNREGISTERED.wait(trays).unwrap();
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::{quit_all, restore};
+    use crate::SystrayError;
+    use std::io;
+    use std::mem;
+    use std::ptr;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    // Write end of the pipe the handler wakes the thread with, writing is
+    // about all a signal handler may do. Created with the first handlers and
+    // kept for later ones.
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+    // What the signals did before, while our handlers are installed.
+    static PREVIOUS: Mutex<Vec<(libc::c_int, libc::sigaction)>> = Mutex::new(Vec::new());
+
+    extern "C" fn on_signal(signal: libc::c_int) {
+        let fd = PIPE.load(Ordering::Relaxed);
+        let byte = signal as u8;
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+
+    fn os_error(context: &str) -> SystrayError {
+        SystrayError::Os {
+            context: context.to_string(),
+            source: io::Error::last_os_error(),
+        }
+    }
+
+    // The signal came too late for the trays, it does what it did before.
+    fn pass_on(signal: libc::c_int) {
+        restore();
+        unsafe {
+            libc::raise(signal);
+        }
+    }
+
+    fn start_thread() -> Result<(), SystrayError> {
+        let mut fds = [0 as libc::c_int; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(os_error("Error creating signal pipe"));
+        }
+        PIPE.store(fds[1], Ordering::Relaxed);
+        let read_fd = fds[0];
+        thread::spawn(move || {
+            let mut buf = [0u8; 1];
+            loop {
+                let n = unsafe { libc::read(read_fd, buf.as_mut_ptr() as *mut libc::c_void, 1) };
+                if n == 1 {
+                    if quit_all().is_empty() {
+                        pass_on(libc::c_int::from(buf[0]));
+                    }
+                } else if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                } else {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub fn install() -> Result<(), SystrayError> {
+        if PIPE.load(Ordering::Relaxed) < 0 {
+            start_thread()?;
+        }
+        let mut previous = PREVIOUS.lock().unwrap();
+        for &signal in &SIGNALS {
+            let mut action: libc::sigaction = unsafe { mem::zeroed() };
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            let mut old: libc::sigaction = unsafe { mem::zeroed() };
+            if unsafe { libc::sigaction(signal, &action, &mut old) } != 0 {
+                let e = os_error("Error installing signal handler");
+                drop(previous);
+                uninstall();
+                return Err(e);
+            }
+            previous.push((signal, old));
+        }
+        Ok(())
+    }
+
+    pub fn uninstall() {
+        for (signal, old) in PREVIOUS.lock().unwrap().drain(..) {
+            unsafe {
+                libc::sigaction(signal, &old, ptr::null_mut());
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod imp {
+    use super::{quit_all, wait_until_unregistered};
+    use crate::SystrayError;
+    use std::io;
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT};
+
+    // Windows runs console handlers on a thread of their own, so this can
+    // tell the trays directly. Returning FALSE hands the event to the
+    // handlers installed before, the last of which ends the process.
+    unsafe extern "system" fn on_ctrl(ctrl_type: DWORD) -> BOOL {
+        match ctrl_type {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_CLOSE_EVENT => {
+                let told = quit_all();
+                if told.is_empty() {
+                    return FALSE;
+                }
+                // The process ends as soon as this returns, the trays get
+                // the few seconds Windows allows to run their hooks.
+                if ctrl_type == CTRL_CLOSE_EVENT {
+                    wait_until_unregistered(&told);
+                }
+                TRUE
+            }
+            _ => FALSE,
+        }
+    }
+
+    pub fn install() -> Result<(), SystrayError> {
+        if unsafe { SetConsoleCtrlHandler(Some(on_ctrl), TRUE) } == 0 {
+            return Err(SystrayError::Os {
+                context: "Error installing console handler".to_string(),
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn uninstall() {
+        unsafe {
+            SetConsoleCtrlHandler(Some(on_ctrl), FALSE);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+mod imp {
+    use crate::SystrayError;
+
+    pub fn install() -> Result<(), SystrayError> {
+        Err(SystrayError::unavailable(
+            "Quitting on signals is not supported on this platform",
+        ))
+    }
+
+    pub fn uninstall() {}
+}
+
+// The handlers belong to the whole process, so each test runs in a child
+// process of its own: the test binary again, running only that test.
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Command, Output};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    const CHILD: &str = "SYSTRAY_SIGNALS_TEST_CHILD";
+
+    fn in_child(test: &str, body: fn()) -> Option<Output> {
+        if env::var_os(CHILD).is_some() {
+            body();
+            return None;
+        }
+        let output = Command::new(env::current_exe().unwrap())
+            .args([test, "--exact", "--nocapture"])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        Some(output)
+    }
+
+    fn raise(signal: libc::c_int) {
+        unsafe {
+            libc::raise(signal);
+        }
+        // Time for the signal thread, should the process survive.
+        thread::sleep(Duration::from_secs(2));
+    }
+
+    fn quit_then_restore() {
+        let (tx, rx) = channel();
+        register(TrayId(1), tx).unwrap();
+        raise(libc::SIGTERM);
+        assert!(matches!(rx.try_recv(), Ok(Event::Quit)));
+        println!("tray told");
+        unregister(TrayId(1));
+        raise(libc::SIGTERM);
+    }
+
+    #[test]
+    fn signals_quit_the_trays_then_the_process() {
+        let test = "signals::tests::signals_quit_the_trays_then_the_process";
+        if let Some(output) = in_child(test, quit_then_restore) {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(stdout.contains("tray told"), "{}", stdout);
+            assert_eq!(output.status.signal(), Some(libc::SIGTERM));
+        }
+    }
+
+    fn reraise_without_trays() {
+        let (tx, rx) = channel();
+        register(TrayId(2), tx).unwrap();
+        // The tray is gone, but the handlers are still installed.
+        drop(rx);
+        raise(libc::SIGINT);
+    }
+
+    #[test]
+    fn signals_without_trays_are_raised_again() {
+        let test = "signals::tests::signals_without_trays_are_raised_again";
+        if let Some(output) = in_child(test, reraise_without_trays) {
+            assert_eq!(output.status.signal(), Some(libc::SIGINT));
+        }
+    }
+}
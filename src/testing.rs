@@ -0,0 +1,601 @@
+//! Stand-ins for the desktop services the Linux backend talks to, so that
+//! tests can check what a tray published without a real desktop.
+//!
+//! Everything runs on a private `dbus-daemon` started by `PrivateBus`, which
+//! has to be installed. Make it the session bus before creating the
+//! `Application`:
+//!
+//! ```no_run
+//! use systray::testing::{FakeNotificationServer, FakeWatcher, PrivateBus};
+//! use std::time::Duration;
+//!
+//! let bus = PrivateBus::start().unwrap();
+//! bus.set_as_session_bus();
+//! let watcher = FakeWatcher::start(&bus).unwrap();
+//! let server = FakeNotificationServer::start(&bus).unwrap();
+//!
+//! let app = systray::Application::builder().build().unwrap();
+//! app.notify(&systray::Notification::new("Build failed", "3 errors"))
+//!     .unwrap();
+//! assert_eq!(server.wait_for_notifications(1, Duration::from_secs(1))[0].summary, "Build failed");
+//!
+//! let item = watcher.wait_for_item(Duration::from_secs(1)).unwrap();
+//! let menu = item.menu().unwrap();
+//! item.click(menu.children[0].id).unwrap();
+//! ```
+//!
+//! Only available on Linux, with the `testing` feature.
+use crate::{CloseReason, SystrayError};
+use dbus::arg::{ArgType, PropMap, RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use dbus::channel::{Channel, MatchingReceiver, Sender as DBusSender};
+use dbus::message::MatchRule;
+use dbus::Message;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::io::{self, BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const DBUSMENU_INTERFACE: &str = "com.canonical.dbusmenu";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Polls `f` until it returns something or `timeout` has passed.
+fn poll<T, F>(timeout: Duration, mut f: F) -> Option<T>
+where
+    F: FnMut() -> Option<T>,
+{
+    let start = Instant::now();
+    loop {
+        if let Some(t) = f() {
+            return Some(t);
+        }
+        if start.elapsed() > timeout {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// A `dbus-daemon` of its own, killed when dropped.
+pub struct PrivateBus {
+    daemon: Child,
+    address: String,
+}
+
+impl PrivateBus {
+    pub fn start() -> Result<PrivateBus, SystrayError> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| SystrayError::Os {
+                context: "Error starting dbus-daemon".to_string(),
+                source: e,
+            })?;
+        let mut address = String::new();
+        let read = BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .and_then(|_| match address.trim() {
+                "" => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "dbus-daemon exited without printing its address",
+                )),
+                _ => Ok(()),
+            });
+        if let Err(e) = read {
+            daemon.kill().ok();
+            daemon.wait().ok();
+            return Err(SystrayError::Os {
+                context: "Error reading the address of dbus-daemon".to_string(),
+                source: e,
+            });
+        }
+        Ok(PrivateBus {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Points `DBUS_SESSION_BUS_ADDRESS` at this bus, for everything
+    /// connecting to the session bus from now on.
+    pub fn set_as_session_bus(&self) {
+        env::set_var("DBUS_SESSION_BUS_ADDRESS", &self.address);
+    }
+
+    fn connect(&self) -> Result<Connection, SystrayError> {
+        connect(&self.address)
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+    }
+}
+
+fn connect(address: &str) -> Result<Connection, SystrayError> {
+    let mut channel = Channel::open_private(address).map_err(SystrayError::dbus)?;
+    channel.register().map_err(SystrayError::dbus)?;
+    Ok(Connection::from(channel))
+}
+
+type Job = Box<dyn FnOnce(&Connection) + Send>;
+// (icon name, icon pixmaps, title, description)
+type ToolTip = (String, Vec<(i32, i32, Vec<u8>)>, String, String);
+// (id, properties, children)
+type Layout = (i32, PropMap, Vec<Variant<Box<dyn RefArg>>>);
+
+// Serves method calls on `path` with `handler` from a thread of its own. The
+// thread also runs the jobs sent through the returned channel, which is how
+// signals are emitted, and exits when the channel is dropped.
+fn serve<F>(
+    conn: Connection,
+    name: &str,
+    path: &'static str,
+    handler: F,
+) -> Result<Sender<Job>, SystrayError>
+where
+    F: Fn(&Message) -> Result<Message, String> + Send + 'static,
+{
+    conn.request_name(name, false, true, true)
+        .map_err(SystrayError::dbus)?;
+    conn.start_receive(
+        MatchRule::new_method_call().with_path(path),
+        Box::new(move |msg, conn| {
+            let reply = handler(&msg).unwrap_or_else(|e| {
+                let text = CString::new(e).unwrap_or_default();
+                msg.error(&"org.freedesktop.DBus.Error.Failed".into(), &text)
+            });
+            conn.send(reply).ok();
+            true
+        }),
+    );
+    let (jobs, rx) = channel::<Job>();
+    thread::spawn(move || loop {
+        match rx.try_recv() {
+            Ok(job) => {
+                job(&conn);
+                continue;
+            }
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+        if conn.process(Duration::from_millis(10)).is_err() {
+            break;
+        }
+    });
+    Ok(jobs)
+}
+
+fn unknown_method(msg: &Message) -> String {
+    format!(
+        "Unknown method {}.{}",
+        msg.interface().map(|i| i.to_string()).unwrap_or_default(),
+        msg.member().map(|m| m.to_string()).unwrap_or_default()
+    )
+}
+
+/// A `Notify` call received by `FakeNotificationServer`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotifyCall {
+    /// Id the server returned.
+    pub id: u32,
+    pub app_name: String,
+    pub replaces_id: u32,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    /// Alternating action keys and labels, as sent over the bus.
+    pub actions: Vec<String>,
+    pub urgency: Option<u8>,
+    pub expire_timeout: i32,
+}
+
+/// An `org.freedesktop.Notifications` server recording every notification.
+pub struct FakeNotificationServer {
+    calls: Arc<Mutex<Vec<NotifyCall>>>,
+    jobs: Sender<Job>,
+}
+
+impl FakeNotificationServer {
+    pub fn start(bus: &PrivateBus) -> Result<FakeNotificationServer, SystrayError> {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let jobs = serve(
+            bus.connect()?,
+            NOTIFICATIONS_NAME,
+            NOTIFICATIONS_PATH,
+            move |msg| match msg.member().as_deref() {
+                Some("Notify") => {
+                    let (
+                        app_name,
+                        replaces_id,
+                        app_icon,
+                        summary,
+                        body,
+                        actions,
+                        hints,
+                        expire_timeout,
+                    ): (
+                        String,
+                        u32,
+                        String,
+                        String,
+                        String,
+                        Vec<String>,
+                        PropMap,
+                        i32,
+                    ) = msg.read_all().map_err(|e| e.to_string())?;
+                    let mut calls = recorded.lock().unwrap();
+                    let id = if replaces_id != 0 {
+                        replaces_id
+                    } else {
+                        calls.len() as u32 + 1
+                    };
+                    calls.push(NotifyCall {
+                        id,
+                        app_name,
+                        replaces_id,
+                        app_icon,
+                        summary,
+                        body,
+                        actions,
+                        urgency: hints
+                            .get("urgency")
+                            .and_then(|u| u.0.as_u64())
+                            .map(|u| u as u8),
+                        expire_timeout,
+                    });
+                    Ok(msg.method_return().append1(id))
+                }
+                Some("CloseNotification") => Ok(msg.method_return()),
+                Some("GetCapabilities") => {
+                    Ok(msg
+                        .method_return()
+                        .append1(vec!["actions", "body", "body-markup"]))
+                }
+                Some("GetServerInformation") => Ok(msg
+                    .method_return()
+                    .append2("systray-testing", "systray")
+                    .append2(env!("CARGO_PKG_VERSION"), "1.2")),
+                _ => Err(unknown_method(msg)),
+            },
+        )?;
+        Ok(FakeNotificationServer { calls, jobs })
+    }
+
+    /// All notifications received so far.
+    pub fn notifications(&self) -> Vec<NotifyCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Waits until at least `count` notifications were received, and returns
+    /// them all.
+    pub fn wait_for_notifications(&self, count: usize, timeout: Duration) -> Vec<NotifyCall> {
+        poll(timeout, || {
+            let calls = self.notifications();
+            if calls.len() >= count {
+                Some(calls)
+            } else {
+                None
+            }
+        })
+        .unwrap_or_else(|| self.notifications())
+    }
+
+    /// Acts as if the user clicked the action `key` of notification `id`.
+    pub fn invoke_action(&self, id: u32, key: &str) {
+        let key = key.to_owned();
+        self.emit(move || {
+            Message::new_signal(NOTIFICATIONS_PATH, NOTIFICATIONS_NAME, "ActionInvoked")
+                .unwrap()
+                .append2(id, key)
+        });
+    }
+
+    /// Closes notification `id`.
+    pub fn close(&self, id: u32, reason: CloseReason) {
+        let code: u32 = match reason {
+            CloseReason::Expired => 1,
+            CloseReason::Dismissed => 2,
+            CloseReason::Closed => 3,
+            CloseReason::Undefined => 4,
+        };
+        self.emit(move || {
+            Message::new_signal(NOTIFICATIONS_PATH, NOTIFICATIONS_NAME, "NotificationClosed")
+                .unwrap()
+                .append2(id, code)
+        });
+    }
+
+    fn emit<F>(&self, signal: F)
+    where
+        F: FnOnce() -> Message + Send + 'static,
+    {
+        self.jobs
+            .send(Box::new(move |conn: &Connection| {
+                conn.send(signal()).ok();
+            }))
+            .ok();
+    }
+}
+
+/// An `org.kde.StatusNotifierWatcher` recording every registered item.
+pub struct FakeWatcher {
+    address: String,
+    items: Arc<Mutex<Vec<(String, String)>>>,
+    _jobs: Sender<Job>,
+}
+
+impl FakeWatcher {
+    /// Starts a watcher with a StatusNotifierHost registered, as on a
+    /// desktop with a panel.
+    pub fn start(bus: &PrivateBus) -> Result<FakeWatcher, SystrayError> {
+        FakeWatcher::start_with_host(bus, true)
+    }
+
+    /// Starts a watcher that reports no StatusNotifierHost, as when the panel
+    /// has gone away.
+    pub fn without_host(bus: &PrivateBus) -> Result<FakeWatcher, SystrayError> {
+        FakeWatcher::start_with_host(bus, false)
+    }
+
+    fn start_with_host(bus: &PrivateBus, host: bool) -> Result<FakeWatcher, SystrayError> {
+        let items = Arc::new(Mutex::new(Vec::new()));
+        let registered = items.clone();
+        let jobs = serve(
+            bus.connect()?,
+            WATCHER_NAME,
+            WATCHER_PATH,
+            move |msg| match msg.member().as_deref() {
+                Some("RegisterStatusNotifierItem") => {
+                    let service: String = msg.read1().map_err(|e| e.to_string())?;
+                    let sender = msg.sender().map(|s| s.to_string()).unwrap_or_default();
+                    // libappindicator registers an object path on its own
+                    // connection, others register their bus name.
+                    let item = if service.starts_with('/') {
+                        (sender, service)
+                    } else {
+                        (service, "/StatusNotifierItem".to_string())
+                    };
+                    registered.lock().unwrap().push(item);
+                    Ok(msg.method_return())
+                }
+                Some("RegisterStatusNotifierHost") => Ok(msg.method_return()),
+                Some("Get") => {
+                    let (_, property): (String, String) = msg.read2().map_err(|e| e.to_string())?;
+                    let value: Box<dyn RefArg> = match property.as_str() {
+                        "IsStatusNotifierHostRegistered" => Box::new(host),
+                        "ProtocolVersion" => Box::new(0i32),
+                        "RegisteredStatusNotifierItems" => Box::new(
+                            registered
+                                .lock()
+                                .unwrap()
+                                .iter()
+                                .map(|(service, path)| format!("{}{}", service, path))
+                                .collect::<Vec<_>>(),
+                        ),
+                        _ => return Err(format!("Unknown property {}", property)),
+                    };
+                    Ok(msg.method_return().append1(Variant(value)))
+                }
+                _ => Err(unknown_method(msg)),
+            },
+        )?;
+        Ok(FakeWatcher {
+            address: bus.address().to_string(),
+            items,
+            _jobs: jobs,
+        })
+    }
+
+    /// Items registered so far, as `(bus name, object path)`.
+    pub fn items(&self) -> Vec<(String, String)> {
+        self.items.lock().unwrap().clone()
+    }
+
+    /// Waits for an item to be registered, and returns a handle to the first
+    /// one.
+    pub fn wait_for_item(&self, timeout: Duration) -> Option<StatusNotifierItem> {
+        let (service, path) = poll(timeout, || self.items().into_iter().next())?;
+        let conn = connect(&self.address).ok()?;
+        Some(StatusNotifierItem {
+            conn,
+            service,
+            path,
+        })
+    }
+}
+
+/// A node of a dbusmenu layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MenuNode {
+    pub id: i32,
+    /// Properties of the node. Strings are kept as they are, numbers and
+    /// booleans are converted to strings.
+    pub properties: HashMap<String, String>,
+    pub children: Vec<MenuNode>,
+}
+
+impl MenuNode {
+    pub fn label(&self) -> Option<&str> {
+        self.properties.get("label").map(String::as_str)
+    }
+
+    pub fn is_separator(&self) -> bool {
+        self.properties.get("type").map(String::as_str) == Some("separator")
+    }
+}
+
+fn refarg_to_string(arg: &dyn RefArg) -> String {
+    match arg.arg_type() {
+        ArgType::Variant => arg
+            .as_iter()
+            .and_then(|mut i| i.next().map(refarg_to_string))
+            .unwrap_or_default(),
+        ArgType::String | ArgType::ObjectPath | ArgType::Signature => {
+            arg.as_str().unwrap_or_default().to_string()
+        }
+        ArgType::Boolean => (arg.as_i64() == Some(1)).to_string(),
+        _ => match arg.as_i64() {
+            Some(i) => i.to_string(),
+            None => format!("{:?}", arg),
+        },
+    }
+}
+
+// Reads a `(ia{sv}av)` layout node, possibly wrapped in a variant.
+fn parse_menu_node(arg: &dyn RefArg) -> Option<MenuNode> {
+    if arg.arg_type() == ArgType::Variant {
+        return parse_menu_node(arg.as_iter()?.next()?);
+    }
+    let mut fields = arg.as_iter()?;
+    let id = fields.next()?.as_i64()? as i32;
+    let mut properties = HashMap::new();
+    let mut entries = fields.next()?.as_iter()?;
+    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+        properties.insert(key.as_str()?.to_string(), refarg_to_string(value));
+    }
+    let children = fields
+        .next()?
+        .as_iter()?
+        .filter_map(parse_menu_node)
+        .collect();
+    Some(MenuNode {
+        id,
+        properties,
+        children,
+    })
+}
+
+/// A tray icon registered with `FakeWatcher`.
+pub struct StatusNotifierItem {
+    conn: Connection,
+    service: String,
+    path: String,
+}
+
+impl StatusNotifierItem {
+    fn property<R>(&self, name: &str) -> Result<R, SystrayError>
+    where
+        R: for<'b> dbus::arg::Get<'b> + 'static,
+    {
+        self.conn
+            .with_proxy(&*self.service, &*self.path, TIMEOUT)
+            .get(ITEM_INTERFACE, name)
+            .map_err(SystrayError::dbus)
+    }
+
+    pub fn id(&self) -> Result<String, SystrayError> {
+        self.property("Id")
+    }
+
+    pub fn title(&self) -> Result<String, SystrayError> {
+        self.property("Title")
+    }
+
+    pub fn status(&self) -> Result<String, SystrayError> {
+        self.property("Status")
+    }
+
+    pub fn icon_name(&self) -> Result<String, SystrayError> {
+        self.property("IconName")
+    }
+
+    pub fn attention_icon_name(&self) -> Result<String, SystrayError> {
+        self.property("AttentionIconName")
+    }
+
+    /// Title and description of the tooltip.
+    pub fn tool_tip(&self) -> Result<(String, String), SystrayError> {
+        let (_, _, title, description): ToolTip = self.property("ToolTip")?;
+        Ok((title, description))
+    }
+
+    fn menu_path(&self) -> Result<String, SystrayError> {
+        let path: dbus::Path<'static> = self.property("Menu")?;
+        Ok(path.to_string())
+    }
+
+    /// The whole menu layout, with the root node as returned by dbusmenu.
+    pub fn menu(&self) -> Result<MenuNode, SystrayError> {
+        let menu_path = self.menu_path()?;
+        let (_, layout): (u32, Layout) = self
+            .conn
+            .with_proxy(&*self.service, menu_path, TIMEOUT)
+            .method_call(
+                DBUSMENU_INTERFACE,
+                "GetLayout",
+                (0i32, -1i32, Vec::<String>::new()),
+            )
+            .map_err(SystrayError::dbus)?;
+        let (id, props, children) = layout;
+        Ok(MenuNode {
+            id,
+            properties: props
+                .iter()
+                .map(|(k, v)| (k.clone(), refarg_to_string(v)))
+                .collect(),
+            children: children.iter().filter_map(|c| parse_menu_node(c)).collect(),
+        })
+    }
+
+    /// Acts as if the user clicked the icon.
+    pub fn activate(&self) -> Result<(), SystrayError> {
+        self.conn
+            .with_proxy(&*self.service, &*self.path, TIMEOUT)
+            .method_call(ITEM_INTERFACE, "Activate", (0i32, 0i32))
+            .map_err(SystrayError::dbus)
+    }
+
+    /// Acts as if the user clicked the menu item with dbusmenu id `id`.
+    pub fn click(&self, id: i32) -> Result<(), SystrayError> {
+        self.menu_event(id, "clicked")
+    }
+
+    /// Tells the item that the submenu of the menu item `id` is about to be
+    /// shown, as hosts do before drawing it. Returns whether the item says
+    /// the layout needs to be fetched again.
+    pub fn about_to_show(&self, id: i32) -> Result<bool, SystrayError> {
+        let menu_path = self.menu_path()?;
+        self.conn
+            .with_proxy(&*self.service, menu_path, TIMEOUT)
+            .method_call(DBUSMENU_INTERFACE, "AboutToShow", (id,))
+            .map(|(need_update,): (bool,)| need_update)
+            .map_err(SystrayError::dbus)
+    }
+
+    /// Sends a dbusmenu event, such as `"opened"` or `"closed"`, for the
+    /// menu item `id`.
+    pub fn menu_event(&self, id: i32, event: &str) -> Result<(), SystrayError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+        let menu_path = self.menu_path()?;
+        self.conn
+            .with_proxy(&*self.service, menu_path, TIMEOUT)
+            .method_call(
+                DBUSMENU_INTERFACE,
+                "Event",
+                (id, event, Variant(0i32), timestamp),
+            )
+            .map_err(SystrayError::dbus)
+    }
+}
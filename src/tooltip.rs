@@ -0,0 +1,153 @@
+use crate::Markup;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Tooltip of the tray icon.
+///
+/// Modelled after the `ToolTip` property of StatusNotifierItem, which has a
+/// title, a description and an icon, and becomes that property with
+/// AppIndicator on Linux. Backends that only show plain text use
+/// `to_plain_text` instead.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tooltip {
+    pub title: String,
+    /// Parsed with `Markup::parse`, so it can contain simple formatting.
+    pub body: String,
+    /// Icon name from the icon theme, or path to an icon file.
+    pub icon: Option<String>,
+}
+
+impl Tooltip {
+    pub fn new(title: &str) -> Self {
+        Tooltip {
+            title: title.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_body(mut self, body: &str) -> Self {
+        self.body = body.to_owned();
+        self
+    }
+
+    pub fn with_icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_owned());
+        self
+    }
+
+    pub fn body_markup(&self) -> Markup {
+        Markup::parse(&self.body)
+    }
+
+    /// The title, followed by the body without formatting on the next line if
+    /// there is one. The icon is dropped.
+    pub fn to_plain_text(&self) -> String {
+        let body = self.body_markup().to_plain_text();
+        match (self.title.is_empty(), body.is_empty()) {
+            (_, true) => self.title.clone(),
+            (true, false) => body,
+            (false, false) => format!("{}\n{}", self.title, body),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Tooltip {
+    fn from(title: &'a str) -> Self {
+        Tooltip::new(title)
+    }
+}
+
+impl From<String> for Tooltip {
+    fn from(title: String) -> Self {
+        Tooltip {
+            title,
+            ..Default::default()
+        }
+    }
+}
+
+/// Whether a tooltip is shown in full, as returned by
+/// `Application::set_tooltip`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TooltipFit {
+    Whole,
+    /// Cut at a grapheme boundary to fit the backend, which on Windows takes
+    /// 127 UTF-16 code units.
+    Truncated,
+}
+
+// Cuts `text` down to at most `max` UTF-16 code units, the unit of the fixed
+// size buffers of the win32 API, without splitting a grapheme cluster.
+// Returns whether anything was cut.
+pub(crate) fn truncate_utf16(text: &str, max: usize) -> (&str, bool) {
+    let mut len = 0;
+    let mut units = 0;
+    for grapheme in text.graphemes(true) {
+        let grapheme_units = grapheme.encode_utf16().count();
+        if units + grapheme_units > max {
+            return (&text[..len], true);
+        }
+        units += grapheme_units;
+        len += grapheme.len();
+    }
+    (text, false)
+}
+
+// Copies as much of `text` as fits into a fixed size buffer, followed by the
+// terminating NUL, and returns whether it had to be cut.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub(crate) fn copy_utf16(dest: &mut [u16], text: &str) -> bool {
+    let (text, truncated) = truncate_utf16(text, dest.len() - 1);
+    let mut end = 0;
+    for (i, unit) in text.encode_utf16().enumerate() {
+        dest[i] = unit;
+        end = i + 1;
+    }
+    dest[end] = 0;
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_short_text() {
+        assert_eq!(truncate_utf16("Backup", 6), ("Backup", false));
+        assert_eq!(truncate_utf16("", 0), ("", false));
+    }
+
+    #[test]
+    fn truncate_keeps_grapheme_clusters() {
+        // "e" and a combining acute accent, one grapheme of two units.
+        let text = "cafe\u{301}";
+        assert_eq!(truncate_utf16(text, 5), (text, false));
+        assert_eq!(truncate_utf16(text, 4), ("caf", true));
+        // A family emoji, five code points joined into one grapheme.
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(truncate_utf16(family, 7), ("", true));
+        assert_eq!(truncate_utf16(family, 8), (family, false));
+    }
+
+    #[test]
+    fn truncate_keeps_surrogate_pairs() {
+        // Outside the BMP, two units each.
+        assert_eq!(truncate_utf16("a\u{1F600}", 2), ("a", true));
+        assert_eq!(truncate_utf16("a\u{1F600}", 3), ("a\u{1F600}", false));
+    }
+
+    #[test]
+    fn copy_terminates_with_nul() {
+        let mut buffer = [0xffff; 8];
+        assert!(!copy_utf16(&mut buffer, "Tray"));
+        assert_eq!(buffer[..5], [84, 114, 97, 121, 0]);
+
+        // Leaves the last unit for the NUL.
+        let mut buffer = [0xffff; 4];
+        assert!(copy_utf16(&mut buffer, "Tray"));
+        assert_eq!(buffer, [84, 114, 97, 0]);
+
+        let mut buffer = [0xffff; 4];
+        assert!(copy_utf16(&mut buffer, "ab\u{1F600}"));
+        assert_eq!(buffer[..3], [97, 98, 0]);
+    }
+}
@@ -0,0 +1,59 @@
+// Helpers shared by the integration tests, which need programs that may not
+// be installed and skip themselves without them.
+#![allow(dead_code)]
+
+use std::env;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+pub fn on_path(program: &str) -> bool {
+    let paths = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&paths).any(|dir| dir.join(program).is_file())
+}
+
+/// An X server of its own, killed when dropped.
+pub struct Xvfb {
+    server: Child,
+    pub display: String,
+}
+
+impl Xvfb {
+    /// Starts Xvfb on a free display, which it prints with -displayfd.
+    pub fn start() -> Xvfb {
+        let mut server = Command::new("Xvfb")
+            .args([
+                "-displayfd",
+                "1",
+                "-nolisten",
+                "tcp",
+                "-screen",
+                "0",
+                "640x480x24",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Can't start Xvfb");
+        let mut display = String::new();
+        BufReader::new(server.stdout.take().unwrap())
+            .read_line(&mut display)
+            .expect("Xvfb didn't print its display");
+        Xvfb {
+            server,
+            display: format!(":{}", display.trim()),
+        }
+    }
+
+    /// Makes this the display GTK connects to, which it does once per
+    /// process, when the first tray is built.
+    pub fn set_as_display(&self) {
+        env::set_var("DISPLAY", &self.display);
+    }
+}
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        self.server.kill().ok();
+        self.server.wait().ok();
+    }
+}
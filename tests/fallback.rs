@@ -0,0 +1,40 @@
+// Picking the backend when a StatusNotifierWatcher runs without a host, on a
+// private D-Bus and Xvfb. Skipped when either isn't installed.
+#![cfg(all(target_os = "linux", feature = "testing"))]
+
+mod common;
+
+use common::{on_path, Xvfb};
+use std::env;
+use std::thread;
+use std::time::Duration;
+use systray::testing::{FakeWatcher, PrivateBus};
+
+#[test]
+fn falls_back_to_xembed_without_a_host() {
+    if !on_path("dbus-daemon") || !on_path("Xvfb") {
+        eprintln!("dbus-daemon or Xvfb isn't installed, skipping");
+        return;
+    }
+    let xvfb = Xvfb::start();
+    xvfb.set_as_display();
+    let bus = PrivateBus::start().unwrap();
+    bus.set_as_session_bus();
+    let watcher = FakeWatcher::without_host(&bus).unwrap();
+    env::remove_var("SYSTRAY_BACKEND");
+
+    let mut app = systray::Application::builder()
+        .id("systray-fallback")
+        .build()
+        .unwrap();
+    // Menu lifecycle events are only seen where GTK draws the menu.
+    let capabilities = app.capabilities();
+    assert!(capabilities.menu_lifecycle_events);
+    assert!(capabilities.item_tooltips);
+
+    // Nothing is registered with the watcher.
+    thread::sleep(Duration::from_millis(500));
+    assert!(watcher.items().is_empty());
+
+    app.quit().unwrap();
+}
@@ -0,0 +1,116 @@
+// What an Application tells about its menu, for one loaded from a
+// `TrayConfig`, with the XEmbed backend on Xvfb. Skipped when Xvfb isn't
+// installed.
+#![cfg(all(target_os = "linux", feature = "serde"))]
+
+mod common;
+
+use common::{on_path, Xvfb};
+use std::collections::HashMap;
+use std::env;
+use systray::MenuEntry::{Item, Separator, Submenu};
+use systray::{Action, ItemState, Label, MenuEntryConfig, MenuItemId, TrayConfig};
+
+fn config() -> TrayConfig {
+    let radio = |label: &str, checked| MenuEntryConfig::Radio {
+        label: label.to_string(),
+        group: "mode".to_string(),
+        checked,
+        action: None,
+    };
+    TrayConfig {
+        menu: vec![
+            MenuEntryConfig::Item {
+                label: "_Open".to_string(),
+                action: None,
+            },
+            MenuEntryConfig::Group {
+                label: "Mode".to_string(),
+                items: vec![radio("Full", true), radio("Incremental", false)],
+            },
+            MenuEntryConfig::Check {
+                label: "Notify".to_string(),
+                checked: true,
+                action: None,
+            },
+            MenuEntryConfig::Separator,
+        ],
+        ..TrayConfig::default()
+    }
+}
+
+fn checked(checked: Option<bool>) -> ItemState {
+    ItemState {
+        checked,
+        tooltip: None,
+    }
+}
+
+#[test]
+fn tells_what_is_in_the_menu() {
+    if !on_path("Xvfb") {
+        eprintln!("Xvfb isn't installed, skipping");
+        return;
+    }
+    let xvfb = Xvfb::start();
+    xvfb.set_as_display();
+    env::set_var("SYSTRAY_BACKEND", "xembed");
+    let mut app = systray::Application::builder().build().unwrap();
+    let actions: HashMap<&str, Action<(), ()>> = HashMap::new();
+    app.apply_config(&config(), actions).unwrap();
+
+    let tree = app.menu_tree();
+    let (open, mode, notify, separator) = match tree.as_slice() {
+        [Item(open), Submenu(mode), Item(notify), Separator(separator)] => {
+            (open, mode, notify, *separator)
+        }
+        _ => panic!("Unexpected menu {:?}", tree),
+    };
+    assert_eq!(open.label, Label::parse("_Open"));
+    assert_eq!(open.state, checked(None));
+    assert_eq!(notify.label, Label::parse("Notify"));
+    assert_eq!(notify.state, checked(Some(true)));
+
+    let mode_id = mode.id;
+    assert_eq!(mode.label, Label::parse("Mode"));
+    assert!(!mode.dynamic);
+    let radios: Vec<MenuItemId> = mode
+        .entries
+        .iter()
+        .map(|entry| match entry {
+            Item(item) => item.id,
+            _ => panic!("Unexpected entry {:?} in Mode", entry),
+        })
+        .collect();
+    assert_eq!(app.submenu_children(mode_id).unwrap(), mode.entries);
+
+    // Submenus are left out of the items of a menu.
+    assert_eq!(app.menu_items(0).unwrap(), [open.id, notify.id, separator]);
+    assert_eq!(app.menu_items(mode_id).unwrap(), radios);
+
+    assert!(app.contains_menu(0));
+    assert!(app.contains_menu(mode_id));
+    assert!(!app.contains_menu(mode_id + 100));
+
+    assert_eq!(app.item_label(radios[0]).unwrap(), Label::parse("Full"));
+    assert_eq!(
+        app.item_label(radios[1]).unwrap(),
+        Label::parse("Incremental")
+    );
+    assert_eq!(app.item_label(separator).unwrap(), Label::default());
+    assert_eq!(app.item_state(radios[0]).unwrap(), checked(Some(true)));
+    assert_eq!(app.item_state(radios[1]).unwrap(), checked(Some(false)));
+    assert_eq!(app.item_state(separator).unwrap(), checked(None));
+
+    // Changes made after adding show up too.
+    app.set_item_checked(radios[1], true).unwrap();
+    app.set_menu_item_tooltip(open.id, "Shows the main window")
+        .unwrap();
+    assert_eq!(app.item_state(radios[1]).unwrap(), checked(Some(true)));
+    assert_eq!(
+        app.item_state(open.id).unwrap().tooltip.as_deref(),
+        Some("Shows the main window")
+    );
+
+    app.quit().unwrap();
+}
@@ -0,0 +1,252 @@
+// The AppIndicator backend against the fakes of `systray::testing`, on a
+// private D-Bus and Xvfb. Skipped when either isn't installed.
+#![cfg(all(target_os = "linux", feature = "testing"))]
+
+mod common;
+
+use common::{on_path, Xvfb};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use systray::testing::{
+    FakeNotificationServer, FakeWatcher, MenuNode, PrivateBus, StatusNotifierItem,
+};
+use systray::{
+    Application, CloseReason, MenuItemId, Notification, NotificationEvent, SystrayError, Tooltip,
+    Waker,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const ICON: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/rust.ico");
+
+// What the callbacks saw, sent to the test as it happens.
+type Log = Sender<String>;
+
+fn child<'a>(node: &'a MenuNode, label: &str) -> &'a MenuNode {
+    node.children
+        .iter()
+        .find(|c| c.label() == Some(label))
+        .unwrap_or_else(|| panic!("No item {:?} in {:?}", label, node))
+}
+
+fn expect(log: &Receiver<String>, line: &str) {
+    assert_eq!(log.recv_timeout(TIMEOUT).as_deref(), Ok(line));
+}
+
+// Wakes the tray when the test is over, also when it failed, whose on_wake
+// callback then ends the event loop.
+struct StopOnDrop(Waker);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.wake();
+    }
+}
+
+fn check_layout(item: &StatusNotifierItem) {
+    let menu = item.menu().unwrap();
+    let labels: Vec<_> = menu.children.iter().map(|c| c.label()).collect();
+    assert_eq!(
+        labels,
+        [
+            Some("Open"),
+            None,
+            Some("Notify"),
+            Some("More"),
+            Some("Recent"),
+            Some("Quit")
+        ]
+    );
+    assert!(menu.children[1].is_separator());
+    assert_eq!(
+        child(&menu, "Open")
+            .properties
+            .get("accessible-desc")
+            .map(String::as_str),
+        Some("Shows the main window")
+    );
+
+    let notify = child(&menu, "Notify");
+    assert_eq!(notify.properties["toggle-type"], "checkmark");
+    assert_eq!(notify.properties["toggle-state"], "1");
+
+    let more = child(&menu, "More");
+    assert_eq!(more.properties["children-display"], "submenu");
+    child(more, "About");
+}
+
+// The populate callback of a dynamic submenu runs when the host is about to
+// show it, and what it added is then in the layout.
+fn check_dynamic_submenu(item: &StatusNotifierItem, populated: &AtomicUsize) {
+    assert_eq!(populated.load(Ordering::SeqCst), 0);
+    let recent = child(&item.menu().unwrap(), "Recent").id;
+    item.about_to_show(recent).unwrap();
+    assert_eq!(populated.load(Ordering::SeqCst), 1);
+
+    let menu = item.menu().unwrap();
+    let recent = child(&menu, "Recent");
+    assert_eq!(recent.properties["children-display"], "submenu");
+    let labels: Vec<_> = recent.children.iter().map(|c| c.label()).collect();
+    assert_eq!(labels, [Some("notes.txt"), None, Some("Pinned")]);
+    assert_eq!(child(recent, "Pinned").properties["toggle-state"], "1");
+}
+
+// Calls naming a submenu or item that isn't there fail with the id, and
+// leave the menu alone, which `check_layout` then sees.
+fn check_invalid_ids(app: &mut Application<Log>) {
+    let result = app.add_menu_item(999, "Lost", None, |_, _| {});
+    assert!(matches!(result, Err(SystrayError::InvalidMenuId(999))));
+    let result = app.add_menu_separator(999);
+    assert!(matches!(result, Err(SystrayError::InvalidMenuId(999))));
+    let result = app.set_menu_handler(999, |_, _, _| {});
+    assert!(matches!(result, Err(SystrayError::InvalidMenuId(999))));
+
+    let missing: MenuItemId = "999".parse().unwrap();
+    let result = app.set_item_checked(missing, true);
+    assert!(matches!(result, Err(SystrayError::InvalidItemId(id)) if id == missing));
+    let result = app.set_menu_item_tooltip(missing, "Lost");
+    assert!(matches!(result, Err(SystrayError::InvalidItemId(id)) if id == missing));
+    let result = app.item_label(missing);
+    assert!(matches!(result, Err(SystrayError::InvalidItemId(id)) if id == missing));
+
+    // Plain items have no check mark to change.
+    let open = app.menu_items(0).unwrap()[0];
+    let result = app.set_item_checked(open, true);
+    assert!(matches!(result, Err(SystrayError::InvalidItemId(id)) if id == open));
+}
+
+fn check_clicks(item: &StatusNotifierItem, log: &Receiver<String>) {
+    let menu = item.menu().unwrap();
+    item.click(child(&menu, "Open").id).unwrap();
+    expect(log, "open");
+    item.click(child(&menu, "Notify").id).unwrap();
+    expect(log, "notify Some(false)");
+    item.click(child(child(&menu, "More"), "About").id).unwrap();
+    expect(log, "about");
+}
+
+fn check_notifications(server: &FakeNotificationServer, id: u32, log: &Receiver<String>) {
+    let calls = server.wait_for_notifications(1, TIMEOUT);
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].id, id);
+    assert_eq!(calls[0].summary, "Build failed");
+    assert_eq!(calls[0].body, "3 errors");
+    assert_eq!(calls[0].actions, ["default", "Show"]);
+
+    server.invoke_action(id, "default");
+    expect(
+        log,
+        &format!(
+            "{:?}",
+            NotificationEvent::ActionInvoked {
+                id,
+                action: "default".to_string()
+            }
+        ),
+    );
+    server.close(id, CloseReason::Dismissed);
+    expect(
+        log,
+        &format!(
+            "{:?}",
+            NotificationEvent::Closed {
+                id,
+                reason: CloseReason::Dismissed
+            }
+        ),
+    );
+}
+
+#[test]
+fn publishes_to_the_desktop() {
+    if !on_path("dbus-daemon") || !on_path("Xvfb") {
+        eprintln!("dbus-daemon or Xvfb isn't installed, skipping");
+        return;
+    }
+    let xvfb = Xvfb::start();
+    xvfb.set_as_display();
+    let bus = PrivateBus::start().unwrap();
+    bus.set_as_session_bus();
+    let watcher = FakeWatcher::start(&bus).unwrap();
+    let server = FakeNotificationServer::start(&bus).unwrap();
+
+    let (log_tx, log) = channel();
+    let populated = Arc::new(AtomicUsize::new(0));
+    let counter = populated.clone();
+    let mut app: Application<Log> = Application::builder()
+        .id("systray-test")
+        .title("Systray test")
+        .icon(ICON)
+        .tooltip(Tooltip::new("Systray test").with_body("<b>3</b> errors"))
+        .menu(|app| {
+            let open = app.add_menu_item(0, "Open", None, |_, log: &mut Log| {
+                log.send("open".to_string()).ok();
+            })?;
+            app.set_menu_item_tooltip(open, "Shows the main window")?;
+            app.add_menu_separator(0)?;
+            app.add_check_item(0, "Notify", true, |_, log, event| {
+                log.send(format!("notify {:?}", event.checked)).ok();
+            })?;
+            let more = app.add_menu_group(0, "More", None)?;
+            app.add_menu_item(more, "About", None, |_, log| {
+                log.send("about".to_string()).ok();
+            })?;
+            app.add_dynamic_submenu(0, "Recent", move |menu| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                menu.add_item("notes.txt");
+                menu.add_separator();
+                menu.add_check_item("Pinned", true);
+            })?;
+            app.add_menu_item(0, "Quit", None, |app, _| {
+                app.quit().ok();
+            })?;
+            Ok(())
+        })
+        .build_with_state(log_tx)
+        .unwrap();
+    app.on_notification(|_, log, event| {
+        log.send(format!("{:?}", event)).ok();
+    });
+    app.on_wake(|app, _| {
+        app.quit().ok();
+    });
+    check_invalid_ids(&mut app);
+    let notification = Notification {
+        actions: vec![("default".to_string(), "Show".to_string())],
+        ..Notification::new("Build failed", "3 errors")
+    };
+    let id = app.notify(&notification).unwrap();
+    let activate_event = app.capabilities().activate_event;
+
+    let item = watcher.wait_for_item(TIMEOUT).expect("No item registered");
+    assert_eq!(item.id().unwrap(), "systray-test");
+    assert_eq!(item.title().unwrap(), "Systray test");
+    assert_eq!(item.status().unwrap(), "Active");
+    assert!(item.icon_name().unwrap().contains("rust"));
+    assert_eq!(
+        item.tool_tip().unwrap(),
+        ("Systray test".to_string(), "<b>3</b> errors".to_string())
+    );
+
+    let stop = StopOnDrop(app.waker());
+    thread::scope(|scope| {
+        // The clicks need the event loop running here, so they are made
+        // from another thread.
+        scope.spawn(move || {
+            let _stop = stop;
+            check_layout(&item);
+            check_dynamic_submenu(&item, &populated);
+            check_clicks(&item, &log);
+            check_notifications(&server, id, &log);
+            // AppIndicator has no activate event, clicking the icon leaves
+            // the callbacks alone.
+            assert!(!activate_event);
+            item.activate().ok();
+            assert!(log.recv_timeout(Duration::from_millis(200)).is_err());
+            item.click(child(&item.menu().unwrap(), "Quit").id).unwrap();
+        });
+        app.wait_for_message();
+    });
+}
@@ -0,0 +1,118 @@
+// The XEmbed backend against Xvfb and a stand-in tray manager. Skipped when
+// Xvfb isn't installed.
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{on_path, Xvfb};
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    Atom, ClientMessageEvent, ConnectionExt, CreateWindowAux, EventMask, Window, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::{COPY_DEPTH_FROM_PARENT, CURRENT_TIME};
+
+// Opcode of the dock request in _NET_SYSTEM_TRAY_OPCODE messages.
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+
+fn intern(conn: &RustConnection, name: &str) -> Atom {
+    conn.intern_atom(false, name.as_bytes())
+        .unwrap()
+        .reply()
+        .unwrap()
+        .atom
+}
+
+// Owns the tray selection of screen 0, as stalonetray or trayer do, but only
+// takes note of the icons asking to dock.
+struct TrayManager {
+    conn: RustConnection,
+    opcode: Atom,
+}
+
+impl TrayManager {
+    fn new(display: &str) -> TrayManager {
+        let (conn, screen) = x11rb::connect(Some(display)).expect("Can't connect to Xvfb");
+        let root = conn.setup().roots[screen].root;
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+        let selection = intern(&conn, &format!("_NET_SYSTEM_TRAY_S{}", screen));
+        let opcode = intern(&conn, "_NET_SYSTEM_TRAY_OPCODE");
+        let manager = intern(&conn, "MANAGER");
+        conn.set_selection_owner(window, selection, CURRENT_TIME)
+            .unwrap();
+        // Tells icons that are already waiting for a tray about this one.
+        let announce =
+            ClientMessageEvent::new(32, root, manager, [CURRENT_TIME, selection, window, 0, 0]);
+        conn.send_event(false, root, EventMask::STRUCTURE_NOTIFY, announce)
+            .unwrap();
+        conn.flush().unwrap();
+        TrayManager { conn, opcode }
+    }
+
+    // The window of the first icon that asks to dock.
+    fn wait_for_dock(&self, timeout: Duration) -> Option<Window> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            while let Some(event) = self.conn.poll_for_event().unwrap() {
+                if let Event::ClientMessage(message) = event {
+                    let data = message.data.as_data32();
+                    if message.type_ == self.opcode && data[1] == SYSTEM_TRAY_REQUEST_DOCK {
+                        return Some(data[2]);
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+}
+
+#[test]
+fn docks_into_the_tray_manager() {
+    if !on_path("Xvfb") {
+        eprintln!("Xvfb isn't installed, skipping");
+        return;
+    }
+    let xvfb = Xvfb::start();
+    let tray = TrayManager::new(&xvfb.display);
+    xvfb.set_as_display();
+    env::set_var("SYSTRAY_BACKEND", "xembed");
+    let icon = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/rust.ico");
+    let mut app = systray::Application::builder()
+        .icon(icon)
+        .tooltip("XEmbed")
+        .build()
+        .unwrap();
+    assert!(app.capabilities().tooltips);
+
+    let window = tray
+        .wait_for_dock(Duration::from_secs(10))
+        .expect("The icon never asked to dock");
+    let geometry = tray
+        .conn
+        .get_geometry(window)
+        .unwrap()
+        .reply()
+        .expect("The docked window doesn't exist");
+    assert!(geometry.width > 0 && geometry.height > 0);
+
+    app.quit().unwrap();
+}